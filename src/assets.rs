@@ -0,0 +1,620 @@
+use crate::loader::{Job, Loaded, Loader};
+use crate::model::{
+    self, texture, DefaultTextures, Material, MaterialParams, MaterialTexture,
+    MaterialTextures, Model,
+};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// Typed reference to an asset owned by the [`AssetManager`]. Handles are
+/// plain indices; ownership is tracked by the manager's reference counts.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Entry<T> {
+    key: String,
+    asset: T,
+    ref_count: usize,
+}
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<Entry<T>>,
+}
+
+/// Reference-counted slots for one asset type, deduplicated by key.
+struct Storage<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    by_key: HashMap<String, Handle<T>>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_key: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    /// Returns the handle for `key` with one more reference, if it is loaded.
+    fn acquire(&mut self, key: &str) -> Option<Handle<T>> {
+        let handle = *self.by_key.get(key)?;
+        self.retain(handle);
+        Some(handle)
+    }
+
+    fn retain(&mut self, handle: Handle<T>) {
+        if let Some(entry) = self.entry_mut(handle) {
+            entry.ref_count += 1;
+        }
+    }
+
+    fn insert(&mut self, key: String, asset: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot { generation: 0, entry: None });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.entry = Some(Entry { key: key.clone(), asset, ref_count: 1 });
+        let handle = Handle {
+            index,
+            generation: slot.generation,
+            _marker: PhantomData,
+        };
+        self.by_key.insert(key, handle);
+        handle
+    }
+
+    /// Drops one reference and returns the asset once nothing refers to it.
+    fn release(&mut self, handle: Handle<T>) -> Option<T> {
+        let entry = self.entry_mut(handle)?;
+        entry.ref_count -= 1;
+        if entry.ref_count > 0 {
+            return None;
+        }
+
+        let slot = &mut self.slots[handle.index as usize];
+        let entry = slot.entry.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        if self.by_key.get(&entry.key) == Some(&handle) {
+            self.by_key.remove(&entry.key);
+        }
+        Some(entry.asset)
+    }
+
+    #[cfg(debug_assertions)]
+    fn find(&self, key: &str) -> Option<Handle<T>> {
+        self.by_key.get(key).copied()
+    }
+
+    /// Stops deduplicating assets whose key starts with `prefix`, so the next
+    /// load creates fresh ones. Existing handles stay valid until released.
+    fn forget_keys_with_prefix(&mut self, prefix: &str) {
+        self.by_key.retain(|key, _| !key.starts_with(prefix));
+    }
+
+    fn entry_mut(&mut self, handle: Handle<T>) -> Option<&mut Entry<T>> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.entry.as_mut())
+    }
+
+    /// Swaps in a new asset behind an existing handle, returning the old one.
+    /// Gives the new asset back if the handle has been released meanwhile.
+    fn replace(&mut self, handle: Handle<T>, asset: T) -> Result<T, T> {
+        match self.entry_mut(handle) {
+            Some(entry) => Ok(std::mem::replace(&mut entry.asset, asset)),
+            None => Err(asset),
+        }
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.entry.as_ref())
+            .map(|entry| &entry.asset)
+    }
+}
+
+/// A GPU texture, by its view, together with the sampler it was created with.
+pub struct Texture {
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+/// A texture used by one material slot. `sampler` overrides the texture's own
+/// sampler, as glTF attaches samplers to texture references rather than images.
+#[derive(Clone)]
+pub struct TextureSlot {
+    pub texture: Handle<Texture>,
+    pub sampler: Option<wgpu::Sampler>,
+}
+
+/// Textures for each PBR slot; empty slots use the shared neutral textures.
+#[derive(Clone)]
+pub struct MaterialSlots<T = TextureSlot> {
+    pub base_color: Option<T>,
+    pub metallic_roughness: Option<T>,
+    pub normal: Option<T>,
+    pub occlusion: Option<T>,
+    pub emissive: Option<T>,
+}
+
+impl<T> Default for MaterialSlots<T> {
+    fn default() -> Self {
+        Self {
+            base_color: None,
+            metallic_roughness: None,
+            normal: None,
+            occlusion: None,
+            emissive: None,
+        }
+    }
+}
+
+impl<T> MaterialSlots<T> {
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> MaterialSlots<U> {
+        MaterialSlots {
+            base_color: self.base_color.map(&mut f),
+            metallic_roughness: self.metallic_roughness.map(&mut f),
+            normal: self.normal.map(&mut f),
+            occlusion: self.occlusion.map(&mut f),
+            emissive: self.emissive.map(&mut f),
+        }
+    }
+}
+
+impl MaterialSlots {
+    fn textures(&self) -> impl Iterator<Item = Handle<Texture>> + '_ {
+        [
+            &self.base_color,
+            &self.metallic_roughness,
+            &self.normal,
+            &self.occlusion,
+            &self.emissive,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|slot| slot.texture)
+    }
+}
+
+/// Owns every model, material and texture on the GPU. Assets are keyed by
+/// their path (or a path-derived key for sub-assets such as glTF images) so
+/// loading the same file twice hands out the same handle, and they are
+/// unloaded once every handle has been released.
+///
+/// Assets requested with the `*_async` methods are decoded on worker threads;
+/// their handles point at placeholders until [`AssetManager::poll_loads`]
+/// uploads the result and swaps it in.
+pub struct AssetManager {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    root: PathBuf,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    defaults: DefaultTextures,
+    textures: Storage<Texture>,
+    materials: Storage<Material>,
+    models: Storage<Model>,
+    material_slots: HashMap<Handle<Material>, MaterialSlots>,
+    loader: Loader,
+}
+
+impl AssetManager {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            root: asset_root(),
+            material_bind_group_layout: Material::create_bind_group_layout(device),
+            defaults: DefaultTextures::new(device, queue),
+            textures: Storage::default(),
+            materials: Storage::default(),
+            models: Storage::default(),
+            material_slots: HashMap::new(),
+            loader: Loader::new(device.features()),
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn material_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_bind_group_layout
+    }
+
+    /// Resolves a path relative to the asset directory. Absolute paths are
+    /// returned unchanged.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn model(&self, handle: Handle<Model>) -> Option<&Model> {
+        self.models.get(handle)
+    }
+
+    pub fn material(&self, handle: Handle<Material>) -> Option<&Material> {
+        self.materials.get(handle)
+    }
+
+    /// Starts loading a glTF model in the background. The handle shows a
+    /// placeholder box until the model is ready.
+    pub fn load_model_async(&mut self, path: impl AsRef<Path>) -> Handle<Model> {
+        let path = self.resolve(path);
+        let key = path.to_string_lossy().into_owned();
+        if let Some(handle) = self.models.acquire(&key) {
+            return handle;
+        }
+        let placeholder = Model::placeholder(self);
+        let handle = self.models.insert(key, placeholder);
+        self.loader.submit(Job::Model(handle, path));
+        handle
+    }
+
+    /// Registers a model built in code.
+    pub fn add_model(&mut self, key: &str, model: Model) -> Handle<Model> {
+        if let Some(handle) = self.models.acquire(key) {
+            log::warn!("model '{}' is already loaded, keeping the existing one", key);
+            self.release_materials(&model.materials);
+            return handle;
+        }
+        self.models.insert(key.to_string(), model)
+    }
+
+    /// Starts loading a texture in the background. Materials using the handle
+    /// show a grey placeholder until the image is ready.
+    pub fn load_texture_async(&mut self, path: impl AsRef<Path>) -> Handle<Texture> {
+        let path = self.resolve(path);
+        let key = path.to_string_lossy().into_owned();
+        if let Some(handle) = self.textures.acquire(&key) {
+            return handle;
+        }
+        let placeholder = texture::ImageData::new(1, 1, vec![128, 128, 128, 255], true);
+        let handle = self.add_texture(&key, &placeholder);
+        self.loader.submit(Job::Texture(handle, path));
+        handle
+    }
+
+    /// Uploads everything the worker threads have finished decoding. Failed
+    /// loads are logged and keep their placeholder.
+    pub fn poll_loads(&mut self) {
+        while let Some(loaded) = self.loader.try_recv() {
+            match loaded {
+                Loaded::Model(handle, path, Ok(data)) => {
+                    // Materials and images of a reloaded file must not resolve
+                    // to the copies the previous version created.
+                    let prefix = format!("{}#", path.display());
+                    self.materials.forget_keys_with_prefix(&prefix);
+                    self.textures.forget_keys_with_prefix(&prefix);
+
+                    let model = model::upload_model(self, data);
+                    match self.models.replace(handle, model) {
+                        Ok(old) | Err(old) => self.release_materials(&old.materials),
+                    }
+                }
+                Loaded::Texture(handle, path, Ok(image)) => {
+                    let key = path.to_string_lossy();
+                    let (view, sampler) = texture::upload(&self.device, &self.queue, &image, &key);
+                    if self.textures.replace(handle, Texture { view, sampler }).is_ok() {
+                        self.rebuild_materials_using(handle);
+                    }
+                }
+                Loaded::Model(_, path, Err(e)) => {
+                    log::error!("failed to load model {}: {}", path.display(), e);
+                }
+                Loaded::Texture(_, path, Err(e)) => {
+                    log::error!("failed to load texture {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Re-reads a changed model or texture file in the background and swaps
+    /// it in behind its existing handle. Returns false if the file isn't loaded.
+    #[cfg(debug_assertions)]
+    pub fn reload(&mut self, path: &Path) -> bool {
+        let key = path.to_string_lossy();
+        if let Some(handle) = self.models.find(&key) {
+            self.loader.submit(Job::Model(handle, path.to_path_buf()));
+            true
+        } else if let Some(handle) = self.textures.find(&key) {
+            self.loader.submit(Job::Texture(handle, path.to_path_buf()));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Creates a texture from decoded pixels, deduplicated by `key`.
+    pub fn add_texture(&mut self, key: &str, image: &texture::ImageData) -> Handle<Texture> {
+        if let Some(handle) = self.textures.acquire(key) {
+            return handle;
+        }
+        let (view, sampler) = texture::upload(&self.device, &self.queue, image, key);
+        self.textures.insert(key.to_string(), Texture { view, sampler })
+    }
+
+    /// Returns the material registered under `key` with one more reference.
+    pub fn acquire_material(&mut self, key: &str) -> Option<Handle<Material>> {
+        self.materials.acquire(key)
+    }
+
+    /// Creates a material that takes over the references held by `slots`.
+    pub fn add_material(
+        &mut self,
+        key: &str,
+        name: &str,
+        params: MaterialParams,
+        slots: MaterialSlots,
+    ) -> Handle<Material> {
+        if let Some(handle) = self.materials.acquire(key) {
+            for texture in slots.textures() {
+                self.release_texture(texture);
+            }
+            return handle;
+        }
+
+        let material = self.build_material(name, params, &slots);
+        let handle = self.materials.insert(key.to_string(), material);
+        self.material_slots.insert(handle, slots);
+        handle
+    }
+
+    fn build_material(&self, name: &str, params: MaterialParams, slots: &MaterialSlots) -> Material {
+        let slot = |slot: &Option<TextureSlot>, fallback: MaterialTexture| match slot
+            .as_ref()
+            .and_then(|s| Some((s, self.textures.get(s.texture)?)))
+        {
+            Some((slot, texture)) => MaterialTexture {
+                view: texture.view.clone(),
+                sampler: slot.sampler.clone().unwrap_or_else(|| texture.sampler.clone()),
+            },
+            None => fallback,
+        };
+        let textures = MaterialTextures {
+            base_color: slot(&slots.base_color, self.defaults.white()),
+            metallic_roughness: slot(&slots.metallic_roughness, self.defaults.white()),
+            normal: slot(&slots.normal, self.defaults.flat_normal()),
+            occlusion: slot(&slots.occlusion, self.defaults.white()),
+            emissive: slot(&slots.emissive, self.defaults.white()),
+        };
+
+        Material::new(
+            &self.device,
+            &self.material_bind_group_layout,
+            name,
+            params,
+            &textures,
+        )
+    }
+
+    /// Recreates the bind groups of every material sampling `texture`, after the
+    /// texture behind the handle has been swapped.
+    fn rebuild_materials_using(&mut self, texture: Handle<Texture>) {
+        let dependents: Vec<_> = self
+            .material_slots
+            .iter()
+            .filter(|(_, slots)| slots.textures().any(|t| t == texture))
+            .map(|(&handle, _)| handle)
+            .collect();
+        for handle in dependents {
+            let (Some(material), Some(slots)) = (self.materials.get(handle), self.material_slots.get(&handle)) else {
+                continue;
+            };
+            let rebuilt = self.build_material(&material.name, material.params, slots);
+            let _ = self.materials.replace(handle, rebuilt);
+        }
+    }
+
+    /// Gives out another reference to a model, for a second owner that
+    /// releases it separately.
+    pub fn retain_model(&mut self, handle: Handle<Model>) -> Handle<Model> {
+        self.models.retain(handle);
+        handle
+    }
+
+    /// Drops a reference to a model, unloading it and releasing its
+    /// materials with the last one.
+    pub fn release_model(&mut self, handle: Handle<Model>) {
+        if let Some(model) = self.models.release(handle) {
+            self.release_materials(&model.materials);
+        }
+    }
+
+    pub fn release_materials(&mut self, handles: &[Handle<Material>]) {
+        for &handle in handles {
+            self.release_material(handle);
+        }
+    }
+
+    pub fn release_material(&mut self, handle: Handle<Material>) {
+        if self.materials.release(handle).is_some()
+            && let Some(slots) = self.material_slots.remove(&handle)
+        {
+            for texture in slots.textures() {
+                self.release_texture(texture);
+            }
+        }
+    }
+
+    pub fn release_texture(&mut self, handle: Handle<Texture>) {
+        self.textures.release(handle);
+    }
+}
+
+/// The `res` directory. `MMO_ASSET_DIR` overrides it; otherwise the one next to
+/// the executable is preferred, falling back to the crate's own for `cargo run`.
+fn asset_root() -> PathBuf {
+    if let Some(dir) = std::env::var_os("MMO_ASSET_DIR") {
+        return PathBuf::from(dir);
+    }
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("res")));
+    match beside_exe {
+        Some(dir) if dir.is_dir() => dir,
+        _ => Path::new(env!("CARGO_MANIFEST_DIR")).join("res"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loading_a_key_twice_shares_the_asset_until_both_release() {
+        let mut storage = Storage::default();
+        let handle = storage.insert("a.png".to_string(), "a");
+        assert_eq!(storage.acquire("a.png"), Some(handle));
+
+        assert_eq!(storage.release(handle), None);
+        assert_eq!(storage.get(handle), Some(&"a"));
+        assert_eq!(storage.release(handle), Some("a"));
+        assert_eq!(storage.get(handle), None);
+        assert_eq!(storage.acquire("a.png"), None);
+    }
+
+    #[test]
+    fn released_slots_are_reused_with_a_new_generation() {
+        let mut storage = Storage::default();
+        let old = storage.insert("a.png".to_string(), "a");
+        storage.release(old);
+        let new = storage.insert("b.png".to_string(), "b");
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert_eq!(storage.get(new), Some(&"b"));
+    }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut storage = Storage::default();
+        let old = storage.insert("a.png".to_string(), "a");
+        storage.release(old);
+        let new = storage.insert("b.png".to_string(), "b");
+
+        assert_eq!(storage.get(old), None);
+        assert_eq!(storage.replace(old, "c"), Err("c"));
+        storage.retain(old);
+        assert_eq!(storage.release(old), None);
+        // The stale calls left the slot's new occupant alone.
+        assert_eq!(storage.release(new), Some("b"));
+    }
+
+    #[test]
+    fn forgotten_keys_load_afresh_without_breaking_old_handles() {
+        let mut storage = Storage::default();
+        let old = storage.insert("model.glb#image0".to_string(), "old");
+        storage.forget_keys_with_prefix("model.glb#");
+        assert_eq!(storage.acquire("model.glb#image0"), None);
+
+        let new = storage.insert("model.glb#image0".to_string(), "new");
+        assert_eq!(storage.get(old), Some(&"old"));
+        // Releasing the old asset mustn't unregister the new one's key.
+        assert_eq!(storage.release(old), Some("old"));
+        assert_eq!(storage.acquire("model.glb#image0"), Some(new));
+    }
+
+    /// An asset manager on whichever adapter the machine has, or `None`
+    /// where there is no GPU, not even a software one.
+    fn manager() -> Option<AssetManager> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).ok()?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()?;
+        Some(AssetManager::new(&device, &queue))
+    }
+
+    #[test]
+    fn releasing_a_model_frees_its_materials_with_the_last_reference() {
+        let Some(mut assets) = manager() else {
+            return;
+        };
+        let model = Model::placeholder(&mut assets);
+        let material = model.materials[0];
+        let handle = assets.add_model("box", model);
+        assert_eq!(assets.retain_model(handle), handle);
+
+        assets.release_model(handle);
+        assert!(assets.model(handle).is_some());
+        assert!(assets.material(material).is_some());
+        assets.release_model(handle);
+        assert!(assets.model(handle).is_none());
+        assert!(assets.material(material).is_none());
+    }
+
+    #[test]
+    fn adding_a_loaded_model_again_keeps_one_copy_of_its_materials() {
+        let Some(mut assets) = manager() else {
+            return;
+        };
+        let model = Model::placeholder(&mut assets);
+        let first = assets.add_model("box", model);
+        let duplicate = Model::placeholder(&mut assets);
+        let second = assets.add_model("box", duplicate);
+        assert_eq!(first, second);
+        let material = assets.model(first).unwrap().materials[0];
+
+        assets.release_model(first);
+        assert!(assets.material(material).is_some());
+        assets.release_model(second);
+        assert!(assets.material(material).is_none());
+    }
+
+    #[test]
+    fn replacing_keeps_the_handle() {
+        let mut storage = Storage::default();
+        let handle = storage.insert("a.png".to_string(), "placeholder");
+        assert_eq!(storage.replace(handle, "loaded"), Ok("placeholder"));
+        assert_eq!(storage.get(handle), Some(&"loaded"));
+    }
+}
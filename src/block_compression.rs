@@ -0,0 +1,922 @@
+//! CPU decoders for the block-compressed formats KTX2 files carry, used when
+//! the GPU can't sample a texture's format directly.
+
+use anyhow::{bail, Result};
+
+/// The block formats that can be decoded to RGBA8. All use 4x4 texel blocks.
+#[derive(Clone, Copy, Debug)]
+pub enum BlockFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4 { signed: bool },
+    Bc5 { signed: bool },
+    Bc7,
+    Etc2Rgb,
+    Etc2RgbA1,
+    Etc2Rgba,
+    EacR11 { signed: bool },
+    EacRg11 { signed: bool },
+}
+
+impl BlockFormat {
+    fn block_size(self) -> usize {
+        match self {
+            Self::Bc1 | Self::Bc4 { .. } | Self::Etc2Rgb | Self::Etc2RgbA1 | Self::EacR11 { .. } => 8,
+            _ => 16,
+        }
+    }
+}
+
+type Block = [[u8; 4]; 16];
+
+/// Decodes one mip level into tightly packed RGBA8 pixels. Channels a format
+/// doesn't store read as they would when sampled on the GPU: 0 for colour and
+/// 255 for alpha. Signed channels are remapped to the unsigned range.
+pub fn decode(format: BlockFormat, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let blocks_x = width.div_ceil(4) as usize;
+    let blocks_y = height.div_ceil(4) as usize;
+    let expected = blocks_x * blocks_y * format.block_size();
+    if data.len() < expected {
+        bail!(
+            "{:?} level of {}x{} has {} bytes, expected {}",
+            format,
+            width,
+            height,
+            data.len(),
+            expected
+        );
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![0; width * height * 4];
+    for (i, block) in data.chunks_exact(format.block_size()).take(blocks_x * blocks_y).enumerate() {
+        let texels = decode_block(format, block);
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for (t, texel) in texels.iter().enumerate() {
+            let (x, y) = (block_x + t % 4, block_y + t / 4);
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+/// Texels come back in row-major order.
+fn decode_block(format: BlockFormat, block: &[u8]) -> Block {
+    match format {
+        BlockFormat::Bc1 => bc1(block, false),
+        BlockFormat::Bc2 => {
+            let mut texels = bc1(&block[8..], true);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = ((alpha >> (4 * i)) & 0xf) as u8 * 17;
+            }
+            texels
+        }
+        BlockFormat::Bc3 => {
+            let mut texels = bc1(&block[8..], true);
+            for (texel, alpha) in texels.iter_mut().zip(bc4(&block[..8], false)) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        BlockFormat::Bc4 { signed } => bc4(block, signed).map(|r| [r, 0, 0, 255]),
+        BlockFormat::Bc5 { signed } => {
+            let red = bc4(&block[..8], signed);
+            let green = bc4(&block[8..], signed);
+            std::array::from_fn(|i| [red[i], green[i], 0, 255])
+        }
+        BlockFormat::Bc7 => bc7(block),
+        BlockFormat::Etc2Rgb => etc2(block, false),
+        BlockFormat::Etc2RgbA1 => etc2(block, true),
+        BlockFormat::Etc2Rgba => {
+            let mut texels = etc2(&block[8..], false);
+            for (texel, alpha) in texels.iter_mut().zip(eac_alpha(&block[..8])) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        BlockFormat::EacR11 { signed } => eac11(block, signed).map(|r| [r, 0, 0, 255]),
+        BlockFormat::EacRg11 { signed } => {
+            let red = eac11(&block[..8], signed);
+            let green = eac11(&block[8..], signed);
+            std::array::from_fn(|i| [red[i], green[i], 0, 255])
+        }
+    }
+}
+
+fn snorm_to_unorm(v: f32) -> u8 {
+    ((v.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0).round() as u8
+}
+
+fn rgb565(c: u16) -> [u32; 3] {
+    let (r, g, b) = ((c >> 11) as u32 & 31, (c >> 5) as u32 & 63, c as u32 & 31);
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// A BC1 colour block. BC2 and BC3 embed the same block but always use the
+/// four-colour palette.
+fn bc1(block: &[u8], four_colour: bool) -> Block {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mix = |w0: u32, w1: u32| {
+        let total = w0 + w1;
+        let channel = |c: usize| ((e0[c] * w0 + e1[c] * w1 + total / 2) / total) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+
+    let palette = if four_colour || c0 > c1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    std::array::from_fn(|i| palette[(indices >> (2 * i)) as usize & 3])
+}
+
+/// A single-channel BC4 block, also used for BC3 alpha and each BC5 channel.
+fn bc4(block: &[u8], signed: bool) -> [u8; 16] {
+    let (e0, e1, min, max) = if signed {
+        let e = |b: u8| (b as i8).max(-127) as i32;
+        (e(block[0]), e(block[1]), -127, 127)
+    } else {
+        (block[0] as i32, block[1] as i32, 0, 255)
+    };
+
+    let mut palette = [e0, e1, 0, 0, 0, 0, min, max];
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * e0 + i as i32 * e1 + 3) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * e0 + i as i32 * e1 + 2) / 5;
+        }
+    }
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| {
+        let value = palette[(indices >> (3 * i)) as usize & 7];
+        if signed {
+            snorm_to_unorm(value as f32 / 127.0)
+        } else {
+            value as u8
+        }
+    })
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index2_bits: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 4,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 3,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 6,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: true,
+        index_bits: 3,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 1,
+        color_bits: 5,
+        alpha_bits: 6,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 3,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 8,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 2,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 7,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 4,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 5,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) & ((1 << count) - 1);
+        self.position += count;
+        value as u32
+    }
+}
+
+fn bc7_interpolate(e0: u32, e1: u32, index: u32, index_bits: u32) -> u8 {
+    let weight = match index_bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    };
+    (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8
+}
+
+fn bc7(block: &[u8]) -> Block {
+    let mode_index = block[0].trailing_zeros() as usize;
+    let Some(mode) = BC7_MODES.get(mode_index) else {
+        // Reserved mode: the format defines these blocks as transparent black.
+        return [[0; 4]; 16];
+    };
+    let mut bits = BitReader { bits: u128::from_le_bytes(block.try_into().unwrap()), position: mode_index as u32 + 1 };
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // Endpoints are stored channel by channel, then subset by subset.
+    let mut endpoints = [[[0u32; 4]; 2]; 3];
+    for channel in 0..4 {
+        let channel_bits = if channel < 3 { mode.color_bits } else { mode.alpha_bits };
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            for endpoint in subset.iter_mut() {
+                endpoint[channel] = bits.read(channel_bits);
+            }
+        }
+    }
+
+    let mut pbits = [[0u32; 2]; 3];
+    for subset in pbits.iter_mut().take(mode.subsets) {
+        if mode.endpoint_pbits {
+            *subset = [bits.read(1), bits.read(1)];
+        } else if mode.shared_pbits {
+            let pbit = bits.read(1);
+            *subset = [pbit, pbit];
+        }
+    }
+
+    for (subset, subset_pbits) in endpoints.iter_mut().zip(pbits).take(mode.subsets) {
+        for (endpoint, pbit) in subset.iter_mut().zip(subset_pbits) {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                let mut width = if channel < 3 { mode.color_bits } else { mode.alpha_bits };
+                if width == 0 {
+                    *value = 255;
+                    continue;
+                }
+                if mode.endpoint_pbits || mode.shared_pbits {
+                    *value = (*value << 1) | pbit;
+                    width += 1;
+                }
+                *value <<= 8 - width;
+                *value |= *value >> width;
+            }
+        }
+    }
+
+    let partitions = match mode.subsets {
+        1 => [0; 16],
+        2 => BC7_PARTITIONS_2[partition],
+        _ => BC7_PARTITIONS_3[partition],
+    };
+    // The first index of each subset is stored with its top bit implied zero.
+    let is_anchor = |i: usize| {
+        i == 0
+            || (mode.subsets == 2 && i == BC7_ANCHORS_2[partition] as usize)
+            || (mode.subsets == 3
+                && (i == BC7_ANCHORS_3_1[partition] as usize || i == BC7_ANCHORS_3_2[partition] as usize))
+    };
+    let mut indices = [0u32; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index_bits - is_anchor(i) as u32);
+    }
+    let mut indices2 = [0u32; 16];
+    if mode.index2_bits > 0 {
+        for (i, index) in indices2.iter_mut().enumerate() {
+            *index = bits.read(mode.index2_bits - (i == 0) as u32);
+        }
+    }
+
+    std::array::from_fn(|i| {
+        let [e0, e1] = endpoints[partitions[i] as usize];
+        let (color_index, color_bits, alpha_index, alpha_bits) = match (mode.index2_bits, index_selection) {
+            (0, _) => (indices[i], mode.index_bits, indices[i], mode.index_bits),
+            (_, 0) => (indices[i], mode.index_bits, indices2[i], mode.index2_bits),
+            _ => (indices2[i], mode.index2_bits, indices[i], mode.index_bits),
+        };
+        let mut texel = [0; 4];
+        for c in 0..3 {
+            texel[c] = bc7_interpolate(e0[c], e1[c], color_index, color_bits);
+        }
+        texel[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_bits);
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => {}
+        }
+        texel
+    })
+}
+
+const ETC_MODIFIERS: [[i32; 2]; 8] = [[2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183]];
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+fn add_clamped(color: [i32; 3], offset: i32) -> [u8; 4] {
+    let channel = |c: i32| (c + offset).clamp(0, 255) as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2]), 255]
+}
+
+/// An ETC2 colour block, which also covers ETC1. In punch-through blocks the
+/// differential bit instead marks the block opaque, and one palette entry is
+/// transparent black when it isn't.
+fn etc2(block: &[u8], punch_through: bool) -> Block {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let field = |offset: u32, count: u32| ((bits >> offset) & ((1 << count) - 1)) as i32;
+    let flag = field(33, 1) == 1;
+    let opaque = !punch_through || flag;
+    let differential = punch_through || flag;
+
+    // Texel indices are stored column by column, high bits in the upper half.
+    let index = |i: usize| {
+        let column_major = (i % 4 * 4 + i / 4) as u32;
+        (field(16 + column_major, 1) << 1) | field(column_major, 1)
+    };
+    let paint = |palette: [[i32; 3]; 4], offsets: [i32; 4]| -> Block {
+        std::array::from_fn(|i| match index(i) {
+            2 if !opaque => [0; 4],
+            n => add_clamped(palette[n as usize], offsets[n as usize]),
+        })
+    };
+    let expand4 = |c: i32| c * 17;
+    let expand5 = |c: i32| (c << 3) | (c >> 2);
+    let expand6 = |c: i32| (c << 2) | (c >> 4);
+    let expand7 = |c: i32| (c << 1) | (c >> 6);
+    let signed3 = |c: i32| if c >= 4 { c - 8 } else { c };
+
+    let (base1, base2) = if differential {
+        let (r, g, b) = (field(59, 5), field(51, 5), field(43, 5));
+        let (dr, dg, db) = (signed3(field(56, 3)), signed3(field(48, 3)), signed3(field(40, 3)));
+
+        if !(0..32).contains(&(r + dr)) {
+            // T mode: one colour plus three around a second one.
+            let c1 = [(field(59, 2) << 2) | field(56, 2), field(52, 4), field(48, 4)].map(expand4);
+            let c2 = [field(44, 4), field(40, 4), field(36, 4)].map(expand4);
+            let d = ETC_DISTANCES[((field(34, 2) << 1) | field(32, 1)) as usize];
+            return paint([c1, c2, c2, c2], [0, d, 0, -d]);
+        }
+        if !(0..32).contains(&(g + dg)) {
+            // H mode: two colours, each split into a pair.
+            let c1 = [field(59, 4), (field(56, 3) << 1) | field(52, 1), (field(51, 1) << 3) | field(47, 3)];
+            let c2 = [field(43, 4), field(39, 4), field(35, 4)];
+            let order = |c: [i32; 3]| (c[0] << 8) | (c[1] << 4) | c[2];
+            let d_index = (field(34, 1) << 2) | (field(32, 1) << 1) | (order(c1) >= order(c2)) as i32;
+            let d = ETC_DISTANCES[d_index as usize];
+            let (c1, c2) = (c1.map(expand4), c2.map(expand4));
+            return paint([c1, c1, c2, c2], [d, -d, d, -d]);
+        }
+        if !(0..32).contains(&(b + db)) {
+            // Planar mode: a gradient across the block, always opaque.
+            let origin = [field(57, 6), (field(56, 1) << 6) | field(49, 6), (field(48, 1) << 5) | (field(43, 2) << 3) | field(39, 3)];
+            let horizontal = [(field(34, 5) << 1) | field(32, 1), field(25, 7), field(19, 6)];
+            let vertical = [field(13, 6), field(6, 7), field(0, 6)];
+            let expand = |c: [i32; 3]| [expand6(c[0]), expand7(c[1]), expand6(c[2])];
+            let (o, h, v) = (expand(origin), expand(horizontal), expand(vertical));
+            return std::array::from_fn(|i| {
+                let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+                let channel = |c: usize| ((x * (h[c] - o[c]) + y * (v[c] - o[c]) + 4 * o[c] + 2) >> 2).clamp(0, 255) as u8;
+                [channel(0), channel(1), channel(2), 255]
+            });
+        }
+        ([r, g, b].map(expand5), [r + dr, g + dg, b + db].map(expand5))
+    } else {
+        (
+            [field(60, 4), field(52, 4), field(44, 4)].map(expand4),
+            [field(56, 4), field(48, 4), field(40, 4)].map(expand4),
+        )
+    };
+
+    let (table1, table2, flip) = (field(37, 3), field(34, 3), field(32, 1) == 1);
+    std::array::from_fn(|i| {
+        let (x, y) = (i % 4, i / 4);
+        let second = if flip { y >= 2 } else { x >= 2 };
+        let (base, table) = if second { (base2, table2) } else { (base1, table1) };
+        let [small, large] = ETC_MODIFIERS[table as usize];
+        match index(i) {
+            0 if !opaque => add_clamped(base, 0),
+            0 => add_clamped(base, small),
+            1 => add_clamped(base, large),
+            2 if !opaque => [0; 4],
+            2 => add_clamped(base, -small),
+            _ => add_clamped(base, -large),
+        }
+    })
+}
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// Splits an EAC block into its base, multiplier and per-texel modifiers, in
+/// row-major order.
+fn eac_fields(block: &[u8]) -> (u8, i32, [i32; 16]) {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let multiplier = (block[1] >> 4) as i32;
+    let table = EAC_MODIFIERS[(block[1] & 0xf) as usize];
+    let modifiers = std::array::from_fn(|i| {
+        let column_major = (i % 4 * 4 + i / 4) as u32;
+        table[((bits >> (45 - 3 * column_major)) & 7) as usize]
+    });
+    (block[0], multiplier, modifiers)
+}
+
+fn eac_alpha(block: &[u8]) -> [u8; 16] {
+    let (base, multiplier, modifiers) = eac_fields(block);
+    modifiers.map(|m| (base as i32 + m * multiplier).clamp(0, 255) as u8)
+}
+
+/// An 11-bit EAC channel, reduced to 8 bits.
+fn eac11(block: &[u8], signed: bool) -> [u8; 16] {
+    let (base, multiplier, modifiers) = eac_fields(block);
+    // A zero multiplier still moves values by the raw modifier, an eighth of a step.
+    let offset = |m: i32| if multiplier == 0 { m } else { m * multiplier * 8 };
+    modifiers.map(|m| {
+        if signed {
+            let base = (base as i8).max(-127) as i32;
+            let value = (base * 8 + offset(m)).clamp(-1023, 1023);
+            snorm_to_unorm(value as f32 / 1023.0)
+        } else {
+            let value = (base as i32 * 8 + 4 + offset(m)).clamp(0, 2047);
+            ((value * 255 + 1023) / 2047) as u8
+        }
+    })
+}
+
+const BC7_PARTITIONS_2: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1],
+    [0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1],
+    [0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1],
+    [0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0],
+    [0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0],
+    [0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0],
+    [0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 1],
+    [0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0],
+    [0, 0, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 1, 0, 0],
+    [0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 0, 1, 0, 0, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0],
+    [0, 1, 1, 1, 0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0],
+    [0, 0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1],
+    [0, 1, 0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0],
+    [0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0],
+    [0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0],
+    [0, 1, 0, 1, 0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0],
+    [0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0, 0, 1],
+    [0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 1],
+    [0, 1, 1, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 1, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 0, 0, 0],
+    [0, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1, 0, 0],
+    [0, 0, 1, 1, 1, 0, 1, 1, 1, 1, 0, 1, 1, 1, 0, 0],
+    [0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0],
+    [0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1],
+    [0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1],
+    [0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0],
+    [0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0],
+    [0, 1, 1, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 1, 1, 0, 1, 1, 0, 0, 1, 0, 0, 1],
+    [0, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 1, 1, 0, 0],
+    [0, 0, 1, 1, 1, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 0],
+    [0, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 0, 0, 1],
+    [0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1],
+    [0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0],
+    [0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 1, 1, 1],
+];
+
+const BC7_PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+const BC7_ANCHORS_2: [u8; 64] = [15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15];
+const BC7_ANCHORS_3_1: [u8; 64] = [3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3];
+const BC7_ANCHORS_3_2: [u8; 64] = [15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texels(format: BlockFormat, block: &[u8]) -> Vec<[u8; 4]> {
+        let rgba = decode(format, block, 4, 4).unwrap();
+        rgba.chunks_exact(4).map(|texel| texel.try_into().unwrap()).collect()
+    }
+
+    fn grey(value: u8) -> [u8; 4] {
+        [value, value, value, 255]
+    }
+
+    fn bc1_block(c0: u16, c1: u16, index: impl Fn(usize) -> u32) -> [u8; 8] {
+        let indices = (0..16).fold(0, |bits, i| bits | index(i) << (2 * i));
+        let mut block = [0; 8];
+        block[..2].copy_from_slice(&c0.to_le_bytes());
+        block[2..4].copy_from_slice(&c1.to_le_bytes());
+        block[4..].copy_from_slice(&indices.to_le_bytes());
+        block
+    }
+
+    fn bc4_block(e0: u8, e1: u8, index: impl Fn(usize) -> u64) -> [u8; 8] {
+        let indices = (0..16).fold(0, |bits, i| bits | index(i) << (3 * i));
+        let mut block = [e0, e1, 0, 0, 0, 0, 0, 0];
+        block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+        block
+    }
+
+    /// Packs `(value, width)` fields least significant bit first, as BC7
+    /// lays them out.
+    fn bc7_block(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, width) in fields {
+            assert!(value < 1 << width);
+            bits |= (value as u128) << position;
+            position += width;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes().to_vec()
+    }
+
+    /// An ETC2 block from `(value, lowest bit)` fields, numbered from the
+    /// least significant bit of the big-endian word, with texel `(x, y)`
+    /// using palette index `index(x, y)`.
+    fn etc_block(fields: &[(u64, u32)], index: impl Fn(usize, usize) -> u64) -> Vec<u8> {
+        let mut bits = fields.iter().fold(0, |bits, &(value, lowest)| bits | value << lowest);
+        for x in 0..4 {
+            for y in 0..4 {
+                let (n, i) = (x * 4 + y, index(x, y));
+                bits |= (i >> 1) << (16 + n) | (i & 1) << n;
+            }
+        }
+        bits.to_be_bytes().to_vec()
+    }
+
+    fn rows(texels: &[[u8; 4]]) -> [[u8; 4]; 4] {
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, texels[i / 4 * 4], "texel {} differs from its row", i);
+        }
+        std::array::from_fn(|y| texels[y * 4])
+    }
+
+    #[test]
+    fn bc1_four_colour_block() {
+        // Pure red over pure blue selects the four-colour palette.
+        let block = bc1_block(0xf800, 0x001f, |i| i as u32 % 4);
+        let palette = [[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]];
+        for (i, texel) in texels(BlockFormat::Bc1, &block).into_iter().enumerate() {
+            assert_eq!(texel, palette[i % 4], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc1_three_colour_block_has_transparent_black() {
+        // Red 16 expands to 132, so the midpoint is exactly 66.
+        let block = bc1_block(0x0000, 0x8000, |i| i as u32 % 4);
+        let palette = [[0, 0, 0, 255], [132, 0, 0, 255], [66, 0, 0, 255], [0, 0, 0, 0]];
+        for (i, texel) in texels(BlockFormat::Bc1, &block).into_iter().enumerate() {
+            assert_eq!(texel, palette[i % 4], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc4_eight_value_block() {
+        let block = bc4_block(200, 60, |i| i as u64 % 8);
+        let palette = [200, 60, 180, 160, 140, 120, 100, 80];
+        for (i, texel) in texels(BlockFormat::Bc4 { signed: false }, &block).into_iter().enumerate() {
+            assert_eq!(texel, [palette[i % 8], 0, 0, 255], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc4_six_value_block_includes_black_and_white() {
+        let block = bc4_block(50, 150, |i| i as u64 % 8);
+        let palette = [50, 150, 70, 90, 110, 130, 0, 255];
+        for (i, texel) in texels(BlockFormat::Bc4 { signed: false }, &block).into_iter().enumerate() {
+            assert_eq!(texel, [palette[i % 8], 0, 0, 255], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn signed_bc4_maps_to_the_unsigned_range() {
+        // -128 is read as -127, so both ends of the range are reachable.
+        let block = bc4_block(0x80, 0x7f, |i| [0, 1, 6, 7][i % 4]);
+        let expected = [0, 255, 0, 255];
+        for (i, texel) in texels(BlockFormat::Bc4 { signed: true }, &block).into_iter().enumerate() {
+            assert_eq!(texel, [expected[i % 4], 0, 0, 255], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_1_two_subsets_with_shared_pbits() {
+        // Partition 0 puts the left two columns in subset 0 and the right
+        // two in subset 1, whose anchor is the last texel.
+        let mut fields = vec![(0b10, 2), (0, 6)];
+        for endpoints in [[0, 63, 32, 16]; 3] {
+            fields.extend(endpoints.map(|e| (e, 6)));
+        }
+        fields.extend([(1, 1), (0, 1)]);
+        for i in 0..16 {
+            let anchor = i == 0 || i == 15;
+            let index = match i {
+                0 | 15 => 3,
+                5 => 7,
+                _ if i % 4 < 2 => 0,
+                _ => 7,
+            };
+            fields.push((index, if anchor { 2 } else { 3 }));
+        }
+
+        let texels = texels(BlockFormat::Bc7, &bc7_block(&fields));
+        for (i, texel) in texels.into_iter().enumerate() {
+            let expected = match i {
+                0 => 109,
+                5 => 255,
+                15 => 102,
+                _ if i % 4 < 2 => 2,
+                _ => 64,
+            };
+            assert_eq!(texel, grey(expected), "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_4_rotation_and_index_selection() {
+        // Rotation 3 swaps blue and alpha; index selection 1 takes colour
+        // from the 3-bit indices and alpha from the 2-bit ones.
+        let mut fields = vec![(0b10000, 5), (3, 2), (1, 1)];
+        fields.extend([(31, 5), (0, 5), (16, 5), (16, 5), (0, 5), (31, 5), (0, 6), (63, 6)]);
+        fields.push((1, 1));
+        fields.extend([(3, 2); 15]);
+        fields.push((0, 2));
+        fields.extend([(0, 3); 15]);
+
+        let texels = texels(BlockFormat::Bc7, &bc7_block(&fields));
+        assert_eq!(texels[0], [255, 132, 84, 0]);
+        for texel in &texels[1..] {
+            assert_eq!(*texel, [255, 132, 255, 0]);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_with_4_bit_indices() {
+        let mut fields = vec![(1 << 6, 7)];
+        fields.extend([(0, 7), (127, 7), (50, 7), (50, 7), (10, 7), (10, 7), (127, 7), (127, 7)]);
+        fields.extend([(0, 1), (1, 1)]);
+        fields.push((0, 3));
+        fields.extend((1..16).map(|i| (i, 4)));
+
+        let red = [0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255];
+        let texels = texels(BlockFormat::Bc7, &bc7_block(&fields));
+        for (i, texel) in texels.into_iter().enumerate() {
+            let upper = (i >= 8) as u8;
+            assert_eq!(texel, [red[i], 100 + upper, 20 + upper, 254 + upper], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn etc2_t_mode() {
+        // Red 0 plus a difference of -3 overflows, selecting T mode.
+        let fields = [(0b01, 59), (1, 58), (0b01, 56), (0xa, 52), (0x3, 48), (8, 44), (8, 40), (8, 36), (0b10, 34), (1, 33), (1, 32)];
+        let block = etc_block(&fields, |_, y| y as u64);
+        let expected = [[85, 170, 51, 255], grey(168), grey(136), grey(104)];
+        assert_eq!(rows(&texels(BlockFormat::Etc2Rgb, &block)), expected);
+    }
+
+    #[test]
+    fn etc2_h_mode() {
+        // Green 0 plus a difference of -4 overflows while red doesn't,
+        // selecting H mode.
+        let fields = [(8, 59), (0b011, 56), (1, 50), (1, 47), (2, 43), (4, 39), (12, 35), (1, 33), (1, 32)];
+        let block = etc_block(&fields, |_, y| y as u64);
+        let expected = [[152, 118, 33, 255], [120, 86, 1, 255], [50, 84, 220, 255], [18, 52, 188, 255]];
+        assert_eq!(rows(&texels(BlockFormat::Etc2Rgb, &block)), expected);
+    }
+
+    #[test]
+    fn etc2_planar_mode() {
+        // Blue 0 plus a difference of -4 overflows, selecting planar mode.
+        // Red ramps across the block, green down it and blue stays at 134.
+        let fields = [(1, 48), (1, 42), (1, 39), (31, 34), (1, 33), (1, 32), (33, 19), (127, 6), (33, 0)];
+        let block = etc_block(&fields, |_, _| 0);
+        let ramp = [0, 64, 128, 191];
+        for (i, texel) in texels(BlockFormat::Etc2Rgb, &block).into_iter().enumerate() {
+            assert_eq!(texel, [ramp[i % 4], ramp[i / 4], 134, 255], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn etc2_punch_through_alpha() {
+        let base = [(16, 59), (8, 51), (4, 43)];
+        let transparent = etc_block(&base, |_, y| y as u64);
+        let expected = [[132, 66, 33, 255], [140, 74, 41, 255], [0; 4], [124, 58, 25, 255]];
+        assert_eq!(rows(&texels(BlockFormat::Etc2RgbA1, &transparent)), expected);
+
+        // With the opaque bit set the block decodes like a differential one.
+        let opaque = etc_block(&[base[0], base[1], base[2], (1, 33)], |_, y| y as u64);
+        let expected = [[134, 68, 35, 255], [140, 74, 41, 255], [130, 64, 31, 255], [124, 58, 25, 255]];
+        assert_eq!(rows(&texels(BlockFormat::Etc2RgbA1, &opaque)), expected);
+    }
+
+    #[test]
+    fn partial_blocks_are_cropped_and_short_data_rejected() {
+        let block = bc1_block(0xf800, 0x001f, |_| 0);
+        assert_eq!(decode(BlockFormat::Bc1, &block, 2, 3).unwrap(), [255, 0, 0, 255].repeat(6));
+        assert!(decode(BlockFormat::Bc1, &block, 8, 4).is_err());
+    }
+}
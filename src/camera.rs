@@ -1,40 +1,173 @@
+use crate::world::World;
 use glam::{Mat4, Quat, Vec3};
 
+/// How quickly the camera catches up with its target, per second. After
+/// `1 / SMOOTHING_RATE` seconds about two thirds of the gap is closed,
+/// whatever the frame rate.
+const SMOOTHING_RATE: f32 = 12.0;
+/// Spacing of the terrain samples along the eye ray.
+const COLLISION_STEP: f32 = 0.25;
+/// Terrain this close to the focus point is ignored, since the focus sits on
+/// the ground.
+const COLLISION_START: f32 = 1.0;
+/// How far in front of the terrain the eye stops.
+const COLLISION_MARGIN: f32 = 0.5;
+/// Height of the first-person eye above the player's feet.
+const EYE_HEIGHT: f32 = 1.6;
+
+/// How the camera is placed. Every mode orbits a pivot at a distance; they
+/// differ in where the pivot is, which keeps switching between them smooth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Around the player, looking at them.
+    Orbit,
+    /// From the player's eyes.
+    FirstPerson,
+    /// Around a point moved freely, for level design and debugging.
+    FreeFly,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pose {
+    focus_point: Vec3,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+}
+
+/// An orbit camera. The public fields are where it is heading; the view is
+/// built from a smoothed pose that follows them in [`OsrsCamera::update`].
 pub struct OsrsCamera {
+    /// What the camera follows: the point orbited, and the feet under the
+    /// first-person eye.
     pub focus_point: Vec3, 
     pub yaw: f32,         
     pub pitch: f32,        
+    /// Zoom of the current mode.
     pub distance: f32,     
+    /// The pivot in free-fly mode.
+    pub fly_position: Vec3,
+    mode: CameraMode,
+    /// Zoom of each mode, kept while it isn't active.
+    distances: [f32; 3],
+    current: Pose,
+    /// The smoothed distance, shortened where terrain is in the way.
+    eye_distance: f32,
 }
 
 impl OsrsCamera {
     pub fn new(focus_point: Vec3) -> Self {
-        Self {
+        let pose = Pose {
             focus_point,
             yaw: 0.0,
-            pitch: 50.0, 
+            pitch: 50.0,
             distance: 10.0,
+        };
+        Self {
+            focus_point: pose.focus_point,
+            yaw: pose.yaw,
+            pitch: pose.pitch,
+            distance: pose.distance,
+            fly_position: pose.focus_point,
+            mode: CameraMode::Orbit,
+            distances: [pose.distance, 0.0, 0.0],
+            current: pose,
+            eye_distance: pose.distance,
         }
     }
 
-    pub fn eye_position(&self) -> Vec3 {
-        let yaw_rad = self.yaw.to_radians();
-        let pitch_rad = self.pitch.to_radians();
-        let rotation = Quat::from_rotation_y(yaw_rad) * Quat::from_rotation_x(-pitch_rad);
-        let position_offset = rotation * Vec3::new(0.0, 0.0, self.distance);
-        self.focus_point + position_offset
+    pub fn mode(&self) -> CameraMode {
+        self.mode
     }
 
-    pub fn build_view_matrix(&self) -> Mat4 {
-        let yaw_rad = self.yaw.to_radians();
-        let pitch_rad = self.pitch.to_radians();
+    /// Switches mode, restoring the zoom the new mode was left at. Free-fly
+    /// starts from where the eye is, so the view doesn't jump.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.distances[self.mode as usize] = self.distance;
+        self.distance = self.distances[mode as usize];
+        if mode == CameraMode::FreeFly {
+            self.fly_position = self.eye_position() - self.direction() * self.distance;
+        }
+        self.mode = mode;
+    }
+
+    /// Where the camera looks when not orbiting, from the target angles so
+    /// movement follows input without waiting for the smoothing.
+    pub fn forward(&self) -> Vec3 {
+        -Self::direction_for(self.yaw, self.pitch)
+    }
+
+    /// Horizontal right of the view, from the target yaw.
+    pub fn right(&self) -> Vec3 {
+        let yaw = self.yaw.to_radians();
+        Vec3::new(yaw.cos(), 0.0, -yaw.sin())
+    }
+
+    fn pivot(&self) -> Vec3 {
+        match self.mode {
+            CameraMode::Orbit => self.focus_point,
+            CameraMode::FirstPerson => self.focus_point + Vec3::Y * EYE_HEIGHT,
+            CameraMode::FreeFly => self.fly_position,
+        }
+    }
 
-        let rotation = Quat::from_rotation_y(yaw_rad) * Quat::from_rotation_x(-pitch_rad);
-        
-        let position_offset = rotation * Vec3::new(0.0, 0.0, self.distance);
-        let eye_position = self.focus_point + position_offset;
+    /// Moves the smoothed pose towards the target and pulls the eye in front
+    /// of any terrain between it and the focus point. Terrain cuts the
+    /// distance immediately; it grows back smoothly. Free-fly passes through
+    /// terrain.
+    pub fn update(&mut self, dt: f32, world: &World) {
+        let t = 1.0 - (-SMOOTHING_RATE * dt).exp();
+        let pivot = self.pivot();
+        let current = &mut self.current;
+        current.focus_point = current.focus_point.lerp(pivot, t);
+        current.yaw += (self.yaw - current.yaw) * t;
+        current.pitch += (self.pitch - current.pitch) * t;
+        current.distance += (self.distance - current.distance) * t;
 
-        Mat4::look_at_rh(eye_position, self.focus_point, Vec3::Y)
+        let allowed = match self.mode {
+            CameraMode::FreeFly => self.current.distance,
+            _ => self.unobstructed_distance(world),
+        };
+        if allowed < self.eye_distance {
+            self.eye_distance = allowed;
+        } else {
+            self.eye_distance += (allowed - self.eye_distance) * t;
+        }
+    }
+
+    /// From the pivot towards the eye, for the smoothed angles.
+    fn direction(&self) -> Vec3 {
+        Self::direction_for(self.current.yaw, self.current.pitch)
+    }
+
+    fn direction_for(yaw: f32, pitch: f32) -> Vec3 {
+        let rotation = Quat::from_rotation_y(yaw.to_radians()) * Quat::from_rotation_x(-pitch.to_radians());
+        rotation * Vec3::Z
+    }
+
+    /// How far the eye can be along the current direction before the ray
+    /// from the focus point passes under the terrain.
+    fn unobstructed_distance(&self, world: &World) -> f32 {
+        let direction = self.direction();
+        let mut along = COLLISION_START;
+        while along < self.current.distance {
+            let point = self.current.focus_point + direction * along;
+            if point.y < world.surface_height(point.x, point.z) {
+                return (along - COLLISION_MARGIN).max(COLLISION_START);
+            }
+            along += COLLISION_STEP;
+        }
+        self.current.distance
+    }
+
+    pub fn eye_position(&self) -> Vec3 {
+        self.current.focus_point + self.direction() * self.eye_distance
+    }
+
+    pub fn build_view_matrix(&self) -> Mat4 {
+        // Looking along the direction rather than at the pivot also works
+        // when the eye is on the pivot.
+        Mat4::look_to_rh(self.eye_position(), -self.direction(), Vec3::Y)
     }
 }
 
@@ -59,7 +192,60 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    /// Vertical field of view in radians.
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
     pub fn build_projection_matrix(&self) -> Mat4 {
         Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::WORLD_SIZE;
+
+    /// A world whose ground is `height(x, z)`, with no water.
+    fn world(height: impl Fn(usize, usize) -> f32) -> World {
+        let heightmap = (0..WORLD_SIZE).map(|x| (0..WORLD_SIZE).map(|z| height(x, z)).collect()).collect();
+        World::with_water_level(heightmap, f32::MIN)
+    }
+
+    /// A camera that has to catch up with a turn, a zoom and a move.
+    fn moved_camera() -> OsrsCamera {
+        let mut camera = OsrsCamera::new(Vec3::new(32.0, 0.0, 32.0));
+        camera.focus_point = Vec3::new(34.0, 0.0, 30.0);
+        camera.yaw = 90.0;
+        camera.pitch = 30.0;
+        camera.distance = 6.0;
+        camera
+    }
+
+    #[test]
+    fn smoothing_does_not_depend_on_the_frame_rate() {
+        let flat = world(|_, _| 0.0);
+        let (mut one_frame, mut two_frames) = (moved_camera(), moved_camera());
+        one_frame.update(0.1, &flat);
+        two_frames.update(0.05, &flat);
+        two_frames.update(0.05, &flat);
+        assert!(one_frame.eye_position().abs_diff_eq(two_frames.eye_position(), 1e-4));
+    }
+
+    #[test]
+    fn eye_stays_above_a_hill_behind_it() {
+        // A ridge rising between the player and the eye.
+        let hill = world(|_, z| if z >= 35 { 8.0 } else { 0.0 });
+        let focus_point = Vec3::new(32.0, 0.0, 32.0);
+        let mut camera = OsrsCamera::new(focus_point);
+        camera.update(0.016, &hill);
+
+        let eye = camera.eye_position();
+        assert!(eye.y > hill.surface_height(eye.x, eye.z));
+        assert!(eye.distance(focus_point) < camera.distance);
+    }
+}
@@ -1,90 +1,339 @@
-use crate::camera::OsrsCamera;
-use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
-use winit::keyboard::{Key, NamedKey};
+use crate::camera::{CameraMode, OsrsCamera};
+use crate::input::{Action, InputMap};
+use crate::settings::{CameraModeSettings, CameraSettings};
+use glam::{Vec2, Vec3};
 
-#[derive(Default)]
-pub struct CameraController {
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-    is_middle_mouse_pressed: bool,
-    
-    rotation_speed: f32,
-    mouse_sensitivity: f32,
-    
-    mouse_delta_x: f32,
-    mouse_delta_y: f32,
-    zoom_delta: f32,
+/// How quickly the fling speed follows the mouse while dragging, per second.
+/// Frames without movement pull it towards zero, so a drag that stops before
+/// the button is released doesn't fling.
+const FLING_SMOOTHING: f32 = 20.0;
+/// Turn speed, in degrees per second, below which a coasting turn stops.
+const REST_SPEED: f32 = 0.5;
+
+/// What turns the camera in one frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrbitInput {
+    /// Held keys, from -1 to 1, as yaw and pitch.
+    pub turn: Vec2,
+    /// Degrees of yaw and pitch the mouse moved while dragging, or `None`
+    /// when not dragging.
+    pub drag: Option<Vec2>,
 }
 
-impl CameraController {
-    pub fn new(rotation_speed: f32, mouse_sensitivity: f32) -> Self {
-        Self {
-            rotation_speed,
-            mouse_sensitivity,
-            ..Default::default()
+/// Turning with momentum. Held keys accelerate the turn up to a top speed and
+/// a drag turns exactly as far as the mouse moved. Once the input ends the
+/// turn coasts on at the speed it had and slows to a stop.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrbitMotion {
+    /// Degrees per second of yaw and pitch.
+    velocity: Vec2,
+    /// Recent speed of the drag, which the turn keeps when it ends.
+    fling: Vec2,
+}
+
+impl OrbitMotion {
+    /// Advances by `dt` seconds and returns how far to turn, in degrees of
+    /// yaw and pitch.
+    pub fn update(&mut self, input: OrbitInput, limits: &CameraModeSettings, dt: f32) -> Vec2 {
+        if input.drag.is_none() {
+            self.velocity += std::mem::take(&mut self.fling);
         }
-    }
 
-    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::KeyboardInput { event: key_event, .. } => {
-                match &key_event.logical_key {
-                    Key::Named(NamedKey::ArrowLeft) => {
-                        self.is_left_pressed = key_event.state == ElementState::Pressed;
-                        true
-                    }
-                    Key::Named(NamedKey::ArrowRight) => {
-                        self.is_right_pressed = key_event.state == ElementState::Pressed;
-                        true
-                    }
-                    _ => false,
+        let damping = (-limits.rotation_damping * dt).exp();
+        let target = input.turn * limits.rotation_speed;
+        let step = limits.rotation_acceleration * dt;
+        for axis in 0..2 {
+            let velocity = &mut self.velocity[axis];
+            if input.turn[axis] != 0.0 {
+                *velocity += (target[axis] - *velocity).clamp(-step, step);
+            } else {
+                *velocity *= damping;
+                if velocity.abs() < REST_SPEED {
+                    *velocity = 0.0;
                 }
             }
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.zoom_delta += match delta {
-                    MouseScrollDelta::LineDelta(_, y) => *y * -1.0,
-                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 * -0.1,
-                };
-                true
-            }
-            WindowEvent::MouseInput { state, button, .. } => {
-                if *button == MouseButton::Middle {
-                    self.is_middle_mouse_pressed = *state == ElementState::Pressed;
-                    true
-                } else {
-                    false
-                }
+        }
+
+        let mut rotation = self.velocity * dt;
+        if let Some(drag) = input.drag {
+            if dt > 0.0 {
+                let t = 1.0 - (-FLING_SMOOTHING * dt).exp();
+                self.fling = self.fling.lerp(drag / dt, t);
             }
-            _ => false,
+            rotation += drag;
         }
+        rotation
+    }
+
+    pub fn stop_yaw(&mut self) {
+        self.velocity.x = 0.0;
+        self.fling.x = 0.0;
     }
 
+    /// Used when the pitch reaches a limit, so it doesn't press against it.
+    pub fn stop_pitch(&mut self) {
+        self.velocity.y = 0.0;
+        self.fling.y = 0.0;
+    }
+}
+
+#[derive(Default)]
+pub struct CameraController {
+    mouse_delta_x: f32,
+    mouse_delta_y: f32,
+    /// Yaw, pitch and zoom from the gamepad, already scaled for the frame.
+    gamepad_look: Vec2,
+    gamepad_zoom: f32,
+    /// How far into the edge band the cursor is, from -1 to 1 per axis with
+    /// y down.
+    edge: Vec2,
+    motion: OrbitMotion,
+}
+
+impl CameraController {
     pub fn process_mouse_motion(&mut self, delta_x: f64, delta_y: f64) {
         self.mouse_delta_x += delta_x as f32;
         self.mouse_delta_y += delta_y as f32;
     }
 
-    pub fn update_camera(&mut self, camera: &mut OsrsCamera) {
-        let zoom_sensitivity = 0.5;
+    /// `look` is in degrees of yaw and pitch, `zoom` in world units.
+    pub fn process_gamepad(&mut self, look: Vec2, zoom: f32) {
+        self.gamepad_look += look;
+        self.gamepad_zoom += zoom;
+    }
+
+    /// Where the cursor is in a window of `size`, or `None` when edge
+    /// rotation shouldn't happen, e.g. when the cursor is outside.
+    pub fn process_cursor(&mut self, cursor: Option<Vec2>, size: Vec2, margin: f32) {
+        self.edge = cursor.map_or(Vec2::ZERO, |cursor| {
+            let near = ((margin - cursor) / margin).clamp(Vec2::ZERO, Vec2::ONE);
+            let far = ((cursor - (size - margin)) / margin).clamp(Vec2::ZERO, Vec2::ONE);
+            far - near
+        });
+    }
 
-        if self.is_left_pressed {
-            camera.yaw -= self.rotation_speed;
+    /// Applies this frame's input to `camera` within the limits of its mode.
+    /// The orbit camera turns with the mouse while dragging; the others
+    /// always look with it.
+    pub fn update_camera(&mut self, camera: &mut OsrsCamera, input: &mut InputMap, settings: &CameraSettings, dt: f32) {
+        for (action, mode) in [(Action::ToggleFreeFly, CameraMode::FreeFly), (Action::ToggleFirstPerson, CameraMode::FirstPerson)] {
+            if input.take_pressed(action) {
+                let mode = if camera.mode() == mode { CameraMode::Orbit } else { mode };
+                camera.set_mode(mode);
+                // Mouse look in the other modes would otherwise fling the
+                // orbit camera.
+                self.motion = OrbitMotion::default();
+                log::info!("{:?} camera", mode);
+            }
         }
-        if self.is_right_pressed {
-            camera.yaw += self.rotation_speed;
+        let limits = settings.mode(camera.mode());
+
+        let axis = |negative, positive| input.is_held(positive) as i32 as f32 - input.is_held(negative) as i32 as f32;
+        let turn = Vec2::new(axis(Action::RotateLeft, Action::RotateRight), axis(Action::PitchDown, Action::PitchUp));
+        let dragging = camera.mode() != CameraMode::Orbit || input.is_held(Action::RotateDrag);
+        let drag = dragging.then(|| Vec2::new(-self.mouse_delta_x, self.mouse_delta_y) * limits.mouse_sensitivity);
+        let mut rotation = self.motion.update(OrbitInput { turn, drag }, limits, dt);
+
+        if !dragging && settings.edge_rotate {
+            rotation += Vec2::new(-self.edge.x, self.edge.y) * settings.edge_speed * dt;
         }
+        rotation += Vec2::new(-self.gamepad_look.x, self.gamepad_look.y);
 
-        if self.is_middle_mouse_pressed {
-            camera.yaw -= self.mouse_delta_x * self.mouse_sensitivity;
-            camera.pitch += self.mouse_delta_y * self.mouse_sensitivity;
-            camera.pitch = camera.pitch.clamp(5.0, 89.0);
+        if input.take_pressed(Action::SnapNorth) {
+            // The nearest whole turn, so the camera swings the short way
+            // round.
+            camera.yaw = (camera.yaw / 360.0).round() * 360.0;
+            self.motion.stop_yaw();
+        } else {
+            camera.yaw += rotation.x;
+        }
+        let pitch = camera.pitch + rotation.y;
+        camera.pitch = pitch.clamp(limits.min_pitch, limits.max_pitch);
+        if camera.pitch != pitch {
+            self.motion.stop_pitch();
         }
 
-        camera.distance += self.zoom_delta * zoom_sensitivity;
-        camera.distance = camera.distance.clamp(2.0, 50.0);
+        let zoom = input.take_amount(Action::ZoomOut) - input.take_amount(Action::ZoomIn);
+        camera.distance += zoom * limits.zoom_step + self.gamepad_zoom;
+        camera.distance = camera.distance.clamp(limits.min_distance, limits.max_distance);
+
+        if camera.mode() == CameraMode::FreeFly {
+            Self::fly(camera, input, settings, dt);
+        }
 
-        self.zoom_delta = 0.0;
         self.mouse_delta_x = 0.0;
         self.mouse_delta_y = 0.0;
+        self.gamepad_look = Vec2::ZERO;
+        self.gamepad_zoom = 0.0;
+    }
+
+    /// Moves the free-fly camera along where it looks.
+    fn fly(camera: &mut OsrsCamera, input: &InputMap, settings: &CameraSettings, dt: f32) {
+        let axes = [
+            (Action::FlyForward, camera.forward()),
+            (Action::FlyBack, -camera.forward()),
+            (Action::FlyRight, camera.right()),
+            (Action::FlyLeft, -camera.right()),
+            (Action::FlyUp, Vec3::Y),
+            (Action::FlyDown, Vec3::NEG_Y),
+        ];
+        let direction: Vec3 = axes.iter().filter(|(action, _)| input.is_held(*action)).map(|(_, axis)| *axis).sum();
+        let mut speed = settings.fly_speed;
+        if input.is_held(Action::FlyFast) {
+            speed *= settings.fly_fast_multiplier;
+        }
+        if input.is_held(Action::FlySlow) {
+            speed *= settings.fly_slow_multiplier;
+        }
+        camera.fly_position += direction.normalize_or_zero() * speed * dt;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Binding, Bindings, GamepadButton};
+
+    const ORBIT: CameraModeSettings = CameraModeSettings::ORBIT;
+
+    fn keys(yaw: f32, pitch: f32) -> OrbitInput {
+        OrbitInput { turn: Vec2::new(yaw, pitch), drag: None }
+    }
+
+    fn drag(yaw: f32, pitch: f32) -> OrbitInput {
+        OrbitInput { turn: Vec2::ZERO, drag: Some(Vec2::new(yaw, pitch)) }
+    }
+
+    /// Feeds `input` for `seconds` at `fps` and returns the total turn.
+    fn run(motion: &mut OrbitMotion, input: OrbitInput, seconds: f32, fps: f32) -> Vec2 {
+        let frames = (seconds * fps).round() as usize;
+        (0..frames).map(|_| motion.update(input, &ORBIT, 1.0 / fps)).sum()
+    }
+
+    #[test]
+    fn held_key_accelerates_to_top_speed() {
+        let mut motion = OrbitMotion::default();
+        let first = motion.update(keys(1.0, 0.0), &ORBIT, 1.0 / 60.0);
+        assert!(first.x > 0.0 && first.x < ORBIT.rotation_speed / 60.0);
+
+        run(&mut motion, keys(1.0, 0.0), 1.0, 60.0);
+        assert_eq!(motion.velocity.x, ORBIT.rotation_speed);
+        assert_eq!(motion.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn key_turn_is_independent_of_frame_rate() {
+        let at = |fps| {
+            let mut motion = OrbitMotion::default();
+            let held = run(&mut motion, keys(1.0, -1.0), 1.0, fps);
+            held + run(&mut motion, keys(0.0, 0.0), 2.0, fps)
+        };
+        let (slow, fast) = (at(30.0), at(144.0));
+        assert!((slow - fast).abs().max_element() < 2.0, "{slow} vs {fast}");
+        assert!(slow.x > 0.0 && slow.y < 0.0);
+    }
+
+    #[test]
+    fn released_key_coasts_to_a_stop() {
+        let mut motion = OrbitMotion::default();
+        run(&mut motion, keys(-1.0, 0.0), 1.0, 60.0);
+        let coast = run(&mut motion, keys(0.0, 0.0), 0.5, 60.0);
+        assert!(coast.x < 0.0, "keeps turning after release");
+        assert!(coast.x.abs() < ORBIT.rotation_speed * 0.5, "slows down");
+
+        run(&mut motion, keys(0.0, 0.0), 3.0, 60.0);
+        assert_eq!(motion.velocity, Vec2::ZERO);
+        assert_eq!(motion.update(keys(0.0, 0.0), &ORBIT, 1.0 / 60.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn reversing_decelerates_before_turning_back() {
+        let mut motion = OrbitMotion::default();
+        run(&mut motion, keys(1.0, 0.0), 1.0, 60.0);
+        let reversed = motion.update(keys(-1.0, 0.0), &ORBIT, 1.0 / 60.0);
+        assert!(reversed.x > 0.0);
+        run(&mut motion, keys(-1.0, 0.0), 1.0, 60.0);
+        assert_eq!(motion.velocity.x, -ORBIT.rotation_speed);
+    }
+
+    #[test]
+    fn drag_follows_the_mouse_exactly() {
+        let mut motion = OrbitMotion::default();
+        assert_eq!(motion.update(drag(3.0, -1.5), &ORBIT, 1.0 / 60.0), Vec2::new(3.0, -1.5));
+        assert_eq!(motion.update(drag(0.0, 0.0), &ORBIT, 1.0 / 60.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn released_drag_flings_on() {
+        let mut motion = OrbitMotion::default();
+        run(&mut motion, drag(2.0, 0.0), 0.5, 60.0);
+        let fling = motion.update(keys(0.0, 0.0), &ORBIT, 1.0 / 60.0);
+        assert!(fling.x > 1.0 && fling.x <= 2.0, "{fling}");
+    }
+
+    #[test]
+    fn drag_held_still_before_release_does_not_fling() {
+        let mut motion = OrbitMotion::default();
+        run(&mut motion, drag(2.0, 0.0), 0.5, 60.0);
+        run(&mut motion, drag(0.0, 0.0), 0.5, 60.0);
+        assert_eq!(motion.update(keys(0.0, 0.0), &ORBIT, 1.0 / 60.0), Vec2::ZERO);
+    }
+
+    fn controller_frame(controller: &mut CameraController, camera: &mut OsrsCamera, input: &mut InputMap) {
+        controller.update_camera(camera, input, &CameraSettings::default(), 1.0 / 60.0);
+        input.end_frame();
+    }
+
+    #[test]
+    fn controller_turns_with_held_bindings() {
+        let mut controller = CameraController::default();
+        let mut camera = OsrsCamera::new(Vec3::ZERO);
+        let mut input = InputMap::new(Bindings::default());
+
+        input.process_gamepad_button(GamepadButton::RightShoulder, true);
+        for _ in 0..30 {
+            controller_frame(&mut controller, &mut camera, &mut input);
+        }
+        let turned = camera.yaw;
+        assert!(turned > 0.0);
+
+        input.process_gamepad_button(GamepadButton::RightShoulder, false);
+        controller_frame(&mut controller, &mut camera, &mut input);
+        assert!(camera.yaw > turned, "coasts after release");
+    }
+
+    #[test]
+    fn pitch_stops_at_its_limit() {
+        let mut controller = CameraController::default();
+        let mut camera = OsrsCamera::new(Vec3::ZERO);
+        let mut bindings = Bindings::default();
+        bindings.rebind(Action::PitchUp, Binding::Gamepad(GamepadButton::DPadLeft));
+        let mut input = InputMap::new(bindings);
+
+        input.process_gamepad_button(GamepadButton::DPadLeft, true);
+        for _ in 0..120 {
+            controller_frame(&mut controller, &mut camera, &mut input);
+        }
+        assert_eq!(camera.pitch, ORBIT.max_pitch);
+        assert_eq!(controller.motion.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn snap_north_picks_the_nearest_whole_turn() {
+        let mut controller = CameraController::default();
+        let mut camera = OsrsCamera::new(Vec3::ZERO);
+        let mut input = InputMap::new(Bindings::default());
+
+        camera.yaw = 530.0;
+        input.process_gamepad_button(GamepadButton::RightShoulder, true);
+        controller_frame(&mut controller, &mut camera, &mut input);
+        input.process_gamepad_button(GamepadButton::RightShoulder, false);
+        input.process_gamepad_button(GamepadButton::RightStick, true);
+        controller_frame(&mut controller, &mut camera, &mut input);
+        assert_eq!(camera.yaw, 360.0);
+
+        input.process_gamepad_button(GamepadButton::RightStick, false);
+        controller_frame(&mut controller, &mut camera, &mut input);
+        assert_eq!(camera.yaw, 360.0, "no turn left over from before the snap");
+    }
+}
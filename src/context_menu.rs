@@ -0,0 +1,75 @@
+use crate::entity::{Entity, EntityId, Interaction};
+use glam::Vec3;
+use winit::dpi::PhysicalPosition;
+
+/// Height of the title row and of each option, in pixels.
+const ROW_HEIGHT: f64 = 19.0;
+const WIDTH: f64 = 160.0;
+/// How far the cursor may leave the menu before it closes.
+const CLOSE_MARGIN: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuAction {
+    WalkHere(Vec3),
+    Interact(EntityId, Interaction),
+    Cancel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuOption {
+    pub label: String,
+    pub action: MenuAction,
+}
+
+/// Everything that can be done with what is under the cursor: the entity's
+/// interactions, walking to the ground behind it, then cancelling. The first
+/// option is the left-click action.
+pub fn options(entity: Option<(EntityId, &Entity)>, ground: Option<Vec3>) -> Vec<MenuOption> {
+    let mut options = Vec::new();
+    if let Some((id, entity)) = entity {
+        options.extend(entity.kind.interactions().iter().map(|&interaction| MenuOption {
+            label: format!("{} {}", interaction.label(), entity.name),
+            action: MenuAction::Interact(id, interaction),
+        }));
+    }
+    if let Some(point) = ground {
+        options.push(MenuOption { label: "Walk here".to_string(), action: MenuAction::WalkHere(point) });
+    }
+    options.push(MenuOption { label: "Cancel".to_string(), action: MenuAction::Cancel });
+    options
+}
+
+/// A right-click menu: a title row with the options listed below it,
+/// centred horizontally on where it was opened.
+pub struct ContextMenu {
+    left: f64,
+    top: f64,
+    options: Vec<MenuOption>,
+}
+
+impl ContextMenu {
+    pub fn new(position: PhysicalPosition<f64>, options: Vec<MenuOption>) -> Self {
+        Self { left: position.x - WIDTH * 0.5, top: position.y, options }
+    }
+
+    pub fn options(&self) -> &[MenuOption] {
+        &self.options
+    }
+
+    /// The option under `cursor`, if it is over one rather than the title or
+    /// outside the menu.
+    pub fn option_at(&self, cursor: PhysicalPosition<f64>) -> Option<&MenuOption> {
+        if !(self.left..self.left + WIDTH).contains(&cursor.x) || cursor.y < self.top {
+            return None;
+        }
+        let row = ((cursor.y - self.top) / ROW_HEIGHT) as usize;
+        self.options.get(row.checked_sub(1)?)
+    }
+
+    /// Whether `cursor` is still close enough to keep the menu open.
+    pub fn is_near(&self, cursor: PhysicalPosition<f64>) -> bool {
+        let height = ROW_HEIGHT * (self.options.len() + 1) as f64;
+        (self.left - CLOSE_MARGIN..=self.left + WIDTH + CLOSE_MARGIN).contains(&cursor.x)
+            && (self.top - CLOSE_MARGIN..=self.top + height + CLOSE_MARGIN).contains(&cursor.y)
+    }
+}
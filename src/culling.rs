@@ -0,0 +1,163 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Contains nothing; the identity for [`Aabb::union`].
+    pub const EMPTY: Self = Self { min: Vec3::splat(f32::INFINITY), max: Vec3::splat(f32::NEG_INFINITY) };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, p| Self { min: aabb.min.min(p), max: aabb.max.max(p) })
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Distance along the ray from `origin` to where it enters the box, or 0
+    /// if it starts inside. `None` if it misses or the box is behind it.
+    pub fn ray_distance(&self, origin: Vec3, direction: Vec3) -> Option<f32> {
+        if self.is_empty() {
+            return None;
+        }
+        let inverse = direction.recip();
+        let (a, b) = ((self.min - origin) * inverse, (self.max - origin) * inverse);
+        let enter = a.min(b).max_element().max(0.0);
+        let exit = a.max(b).min_element();
+        (enter <= exit).then_some(enter)
+    }
+
+    /// The box around this one after `transform`, which is looser than the
+    /// transformed shape for rotations.
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = transform.transform_point3(self.center());
+        let half = (self.max - self.min) * 0.5;
+        let extent = transform.x_axis.truncate().abs() * half.x
+            + transform.y_axis.truncate().abs() * half.y
+            + transform.z_axis.truncate().abs() * half.z;
+        Self { min: center - extent, max: center + extent }
+    }
+}
+
+/// The six planes bounding what the camera sees, each pointing inwards.
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view-projection matrix with wgpu's 0..1
+    /// depth range.
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| p / p.truncate().length());
+        Self { planes }
+    }
+
+    /// Whether any part of the box may be visible. Boxes that straddle a
+    /// corner of the frustum can pass while being outside it.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let farthest = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(farthest) + plane.w >= 0.0
+        })
+    }
+}
+
+/// How many meshes the last frame submitted and how many it skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+impl CullStats {
+    pub fn add(&mut self, visible: bool, meshes: usize) {
+        if visible {
+            self.drawn += meshes as u32;
+        } else {
+            self.culled += meshes as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looking down -Z from the origin with a 90 degree field of view and
+    /// depth from 0.1 to 100.
+    fn frustum() -> Frustum {
+        let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        Frustum::from_view_proj(&(proj * view))
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        Aabb { min: center - half, max: center + half }
+    }
+
+    fn distance(plane: Vec4, point: Vec3) -> f32 {
+        plane.truncate().dot(point) + plane.w
+    }
+
+    #[test]
+    fn planes_are_normalized_and_face_inwards() {
+        let frustum = frustum();
+        let inside = Vec3::new(0.0, 0.0, -10.0);
+        for plane in frustum.planes {
+            assert!((plane.truncate().length() - 1.0).abs() < 1e-5);
+            assert!(distance(plane, inside) > 0.0);
+        }
+        // The side planes are at 45 degrees to the view direction.
+        for plane in &frustum.planes[..4] {
+            assert!((distance(*plane, inside) - 10.0 * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn near_and_far_planes_use_the_zero_to_one_depth_range() {
+        let frustum = frustum();
+        assert!(distance(frustum.planes[4], Vec3::new(0.0, 0.0, -0.1)).abs() < 1e-4);
+        assert!(distance(frustum.planes[5], Vec3::new(0.0, 0.0, -100.0)).abs() < 1e-2);
+    }
+
+    #[test]
+    fn boxes_in_front_are_visible() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&cube(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        // Mostly off to the side, but poking into view.
+        assert!(frustum.intersects(&cube(Vec3::new(11.5, 0.0, -10.0), 2.0)));
+        // Straddling the near plane.
+        assert!(frustum.intersects(&cube(Vec3::ZERO, 0.5)));
+    }
+
+    #[test]
+    fn boxes_outside_are_culled() {
+        let frustum = frustum();
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        assert!(!frustum.intersects(&cube(Vec3::new(15.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, -15.0, -10.0), 1.0)));
+        assert!(!frustum.intersects(&cube(Vec3::new(0.0, 0.0, -110.0), 1.0)));
+        assert!(!frustum.intersects(&Aabb::EMPTY));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::assets::{AssetManager, Handle};
+use crate::lod::Lod;
+use crate::model::Model;
+use glam::{Mat4, Vec3};
+
+/// How close the player has to stand to an entity to interact with it.
+pub const INTERACTION_RANGE: f32 = 1.5;
+
+/// Index of an entity in the scene's entity list.
+pub type EntityId = usize;
+
+/// What sort of thing an entity is, which decides what can be done with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    Npc,
+    Tree,
+}
+
+impl EntityKind {
+    /// The entity's menu options in order; the first is the left-click
+    /// action.
+    pub fn interactions(self) -> &'static [Interaction] {
+        match self {
+            EntityKind::Npc => &[Interaction::TalkTo, Interaction::Attack, Interaction::Examine],
+            EntityKind::Tree => &[Interaction::ChopDown, Interaction::Examine],
+        }
+    }
+}
+
+/// Something the player can do to an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interaction {
+    TalkTo,
+    Attack,
+    ChopDown,
+    Examine,
+}
+
+impl Interaction {
+    pub fn label(self) -> &'static str {
+        match self {
+            Interaction::TalkTo => "Talk-to",
+            Interaction::Attack => "Attack",
+            Interaction::ChopDown => "Chop down",
+            Interaction::Examine => "Examine",
+        }
+    }
+
+    /// Whether the player has to walk up to the entity first.
+    pub fn needs_reach(self) -> bool {
+        self != Interaction::Examine
+    }
+}
+
+/// An NPC or object placed in the world.
+pub struct Entity {
+    pub name: &'static str,
+    pub kind: EntityKind,
+    pub examine: &'static str,
+    pub model: Handle<Model>,
+    pub position: Vec3,
+    /// Orients and scales the model before it is moved to `position`.
+    pub model_transform: Mat4,
+    pub lod: Lod,
+}
+
+impl Entity {
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_translation(self.position) * self.model_transform
+    }
+}
+
+/// The nearest entity whose bounds the ray from `origin` passes through
+/// within `max_distance`, with its distance. Entities whose model hasn't
+/// loaded can't be seen and so can't be picked.
+pub fn pick(
+    entities: &[Entity],
+    assets: &AssetManager,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<(EntityId, f32)> {
+    entities
+        .iter()
+        .enumerate()
+        .filter_map(|(id, entity)| {
+            let bounds = assets.model(entity.model)?.bounds.transformed(&entity.transform());
+            let distance = bounds.ray_distance(origin, direction)?;
+            (distance <= max_distance).then_some((id, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
@@ -0,0 +1,125 @@
+use crate::input::{GamepadButton, InputMap};
+use crate::settings::GamepadSettings;
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use glam::Vec2;
+
+/// Analog state of the active gamepad after deadzones. Stick values are in
+/// -1..1 with up positive; triggers in 0..1.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GamepadAxes {
+    pub left_stick: Vec2,
+    pub right_stick: Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+/// Reads connected gamepads. Buttons go through the action bindings like any
+/// other input; sticks and triggers are returned as axes for the caller to
+/// apply. The gamepad that sent the latest event is the active one.
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    /// Gamepad support is optional, so failing to set it up only logs.
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                log::warn!("gamepad support disabled: {}", e);
+                None
+            }
+        };
+        Self { gilrs, active: None }
+    }
+
+    /// Feeds button changes since the last poll to `input` and returns the
+    /// analog state of the active gamepad.
+    pub fn poll(&mut self, input: &mut InputMap, settings: &GamepadSettings) -> GamepadAxes {
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return GamepadAxes::default();
+        };
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        input.process_gamepad_button(button, true);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        input.process_gamepad_button(button, false);
+                    }
+                }
+                EventType::Connected => {
+                    log::info!("gamepad connected: {}", gilrs.gamepad(event.id).name());
+                }
+                EventType::Disconnected => {
+                    log::info!("gamepad disconnected");
+                    if self.active == Some(event.id) {
+                        self.active = None;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            self.active = Some(event.id);
+        }
+
+        let Some(gamepad) = self.active.and_then(|id| gilrs.connected_gamepad(id)) else {
+            return GamepadAxes::default();
+        };
+        let stick = |x, y| {
+            let value = Vec2::new(gamepad.value(x), gamepad.value(y));
+            let length = value.length();
+            if length == 0.0 {
+                return value;
+            }
+            value * (apply_deadzone(length, settings.stick_deadzone) / length)
+        };
+        let trigger = |button| {
+            let value = gamepad.button_data(button).map_or(0.0, |data| data.value());
+            apply_deadzone(value, settings.trigger_deadzone)
+        };
+        GamepadAxes {
+            left_stick: stick(Axis::LeftStickX, Axis::LeftStickY),
+            right_stick: stick(Axis::RightStickX, Axis::RightStickY),
+            left_trigger: trigger(Button::LeftTrigger2),
+            right_trigger: trigger(Button::RightTrigger2),
+        }
+    }
+}
+
+/// Zeroes a deflection inside `deadzone` and rescales the rest so the output
+/// still starts at zero and reaches full range. Sticks use a radial deadzone,
+/// applied to their length.
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value <= deadzone {
+        return 0.0;
+    }
+    ((value - deadzone) / (1.0 - deadzone)).min(1.0)
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    let button = match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::West => GamepadButton::West,
+        Button::North => GamepadButton::North,
+        Button::LeftTrigger => GamepadButton::LeftShoulder,
+        Button::RightTrigger => GamepadButton::RightShoulder,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    };
+    Some(button)
+}
@@ -0,0 +1,76 @@
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Editors and exporters often write a file in several steps, so a change is
+/// only reported once the file has been quiet for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Path of `shader.wgsl` in the source tree, watched so shader edits apply
+/// without a rebuild.
+pub const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+/// Watches the asset directory and the shader source during development and
+/// reports files that changed on disk.
+pub struct HotReloader {
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<PathBuf>,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl HotReloader {
+    pub fn new(asset_root: &Path) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("file watcher error: {}", e),
+            }
+        })?;
+
+        watcher.watch(asset_root, RecursiveMode::Recursive)?;
+        if let Some(shader_dir) = Path::new(SHADER_PATH).parent() {
+            watcher.watch(shader_dir, RecursiveMode::NonRecursive)?;
+        }
+        log::info!("watching {} for asset changes", asset_root.display());
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Returns the files whose last change has settled since the previous call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        while let Ok(path) = self.events.try_recv() {
+            self.pending.insert(path, now);
+        }
+
+        let ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready
+    }
+}
+
+/// Whether a changed file is the watched shader source.
+pub fn is_shader(path: &Path) -> bool {
+    path.file_name() == Path::new(SHADER_PATH).file_name()
+        && path.parent().and_then(|p| p.canonicalize().ok())
+            == Path::new(SHADER_PATH).parent().and_then(|p| p.canonicalize().ok())
+}
@@ -0,0 +1,502 @@
+use anyhow::{bail, Result};
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// Something the player can do, independent of the input that triggers it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    RotateLeft,
+    RotateRight,
+    /// Raise and lower the orbit camera.
+    PitchUp,
+    PitchDown,
+    /// Held while dragging the mouse to orbit the camera. Shares the right
+    /// mouse button with `OpenMenu`, which only opens on a click.
+    RotateDrag,
+    ZoomIn,
+    ZoomOut,
+    /// Turns the camera to face north.
+    SnapNorth,
+    /// Switch between the orbit camera and the free-fly or first-person one.
+    ToggleFreeFly,
+    ToggleFirstPerson,
+    /// Free-fly movement.
+    FlyForward,
+    FlyBack,
+    FlyLeft,
+    FlyRight,
+    FlyUp,
+    FlyDown,
+    FlyFast,
+    FlySlow,
+    /// Does the default action under the cursor, which on open ground is
+    /// walking there, or picks the option under it in an open menu.
+    MoveTo,
+    /// Opens a menu of everything that can be done under the cursor, when
+    /// released without dragging the camera.
+    OpenMenu,
+    /// Switches the left stick between walking and moving a cursor that
+    /// `MoveTo` clicks with.
+    ToggleVirtualCursor,
+    OpenInventory,
+    /// Shows or hides the tile grid.
+    ToggleGrid,
+    /// Shows or hides the outlines of the player's tile and the tiles under
+    /// the cursor and at the destination.
+    ToggleTileHighlights,
+    ReloadSettings,
+    /// Picks an action by one of its bindings, then makes the next input its
+    /// only binding.
+    RebindControls,
+    /// Switch a render pass on and off, for debugging.
+    ToggleSkyPass,
+    ToggleOpaquePass,
+    ToggleTransparentPass,
+    ToggleWaterPass,
+    ToggleOverlayPass,
+    ToggleBloomBrightPass,
+    ToggleBloomBlurXPass,
+    ToggleBloomBlurYPass,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 35] = [
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::PitchUp,
+        Action::PitchDown,
+        Action::RotateDrag,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::SnapNorth,
+        Action::ToggleFreeFly,
+        Action::ToggleFirstPerson,
+        Action::FlyForward,
+        Action::FlyBack,
+        Action::FlyLeft,
+        Action::FlyRight,
+        Action::FlyUp,
+        Action::FlyDown,
+        Action::FlyFast,
+        Action::FlySlow,
+        Action::MoveTo,
+        Action::OpenMenu,
+        Action::ToggleVirtualCursor,
+        Action::OpenInventory,
+        Action::ToggleGrid,
+        Action::ToggleTileHighlights,
+        Action::ReloadSettings,
+        Action::RebindControls,
+        Action::ToggleSkyPass,
+        Action::ToggleOpaquePass,
+        Action::ToggleTransparentPass,
+        Action::ToggleWaterPass,
+        Action::ToggleOverlayPass,
+        Action::ToggleBloomBrightPass,
+        Action::ToggleBloomBlurXPass,
+        Action::ToggleBloomBlurYPass,
+        Action::Quit,
+    ];
+
+    fn default_bindings(self) -> Vec<Binding> {
+        match self {
+            Action::RotateLeft => vec![Binding::Key(KeyCode::ArrowLeft), Binding::Gamepad(GamepadButton::LeftShoulder)],
+            Action::RotateRight => vec![Binding::Key(KeyCode::ArrowRight), Binding::Gamepad(GamepadButton::RightShoulder)],
+            Action::PitchUp => vec![Binding::Key(KeyCode::ArrowUp)],
+            Action::PitchDown => vec![Binding::Key(KeyCode::ArrowDown)],
+            Action::RotateDrag => vec![Binding::Mouse(MouseButton::Right), Binding::Mouse(MouseButton::Middle)],
+            Action::ZoomIn => vec![Binding::WheelUp, Binding::Gamepad(GamepadButton::DPadUp)],
+            Action::ZoomOut => vec![Binding::WheelDown, Binding::Gamepad(GamepadButton::DPadDown)],
+            Action::SnapNorth => vec![Binding::Key(KeyCode::KeyN), Binding::Gamepad(GamepadButton::RightStick)],
+            Action::ToggleFreeFly => vec![Binding::Key(KeyCode::F11)],
+            Action::ToggleFirstPerson => vec![Binding::Key(KeyCode::KeyV)],
+            Action::FlyForward => vec![Binding::Key(KeyCode::KeyW)],
+            Action::FlyBack => vec![Binding::Key(KeyCode::KeyS)],
+            Action::FlyLeft => vec![Binding::Key(KeyCode::KeyA)],
+            Action::FlyRight => vec![Binding::Key(KeyCode::KeyD)],
+            Action::FlyUp => vec![Binding::Key(KeyCode::KeyE), Binding::Key(KeyCode::Space)],
+            Action::FlyDown => vec![Binding::Key(KeyCode::KeyQ)],
+            Action::FlyFast => vec![Binding::Key(KeyCode::ShiftLeft)],
+            Action::FlySlow => vec![Binding::Key(KeyCode::ControlLeft)],
+            Action::MoveTo => vec![Binding::Mouse(MouseButton::Left), Binding::Gamepad(GamepadButton::South)],
+            Action::OpenMenu => vec![Binding::Mouse(MouseButton::Right), Binding::Gamepad(GamepadButton::West)],
+            Action::ToggleVirtualCursor => vec![Binding::Gamepad(GamepadButton::Select)],
+            Action::OpenInventory => vec![Binding::Key(KeyCode::KeyI), Binding::Gamepad(GamepadButton::North)],
+            Action::ToggleGrid => vec![Binding::Key(KeyCode::KeyG)],
+            Action::ToggleTileHighlights => vec![Binding::Key(KeyCode::KeyH)],
+            Action::ReloadSettings => vec![Binding::Key(KeyCode::F10)],
+            Action::RebindControls => vec![Binding::Key(KeyCode::F12)],
+            Action::ToggleSkyPass => vec![Binding::Key(KeyCode::F1)],
+            Action::ToggleOpaquePass => vec![Binding::Key(KeyCode::F2)],
+            Action::ToggleTransparentPass => vec![Binding::Key(KeyCode::F3)],
+            Action::ToggleWaterPass => vec![Binding::Key(KeyCode::F4)],
+            Action::ToggleOverlayPass => vec![Binding::Key(KeyCode::F5)],
+            Action::ToggleBloomBrightPass => vec![Binding::Key(KeyCode::F6)],
+            Action::ToggleBloomBlurXPass => vec![Binding::Key(KeyCode::F7)],
+            Action::ToggleBloomBlurYPass => vec![Binding::Key(KeyCode::F8)],
+            Action::Quit => vec![Binding::Key(KeyCode::Escape)],
+        }
+    }
+}
+
+/// Gamepad buttons by position, following the Xbox layout for the face
+/// buttons' names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// A physical input that can trigger an action. Keys are bound by position,
+/// so bindings survive keyboard layout changes. Written in the settings file
+/// as e.g. `"Key:KeyW"`, `"Mouse:Middle"`, `"Wheel:Up"` or `"Gamepad:South"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    WheelUp,
+    WheelDown,
+    Gamepad(GamepadButton),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(code) => write!(f, "Key:{:?}", code),
+            Binding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse:{}", button),
+            Binding::Mouse(button) => write!(f, "Mouse:{:?}", button),
+            Binding::WheelUp => write!(f, "Wheel:Up"),
+            Binding::WheelDown => write!(f, "Wheel:Down"),
+            Binding::Gamepad(button) => write!(f, "Gamepad:{:?}", button),
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((device, name)) = s.split_once(':') else {
+            bail!("binding '{}' should look like 'Key:KeyW'", s);
+        };
+        // Key and button names are the enum variants, which serde can parse
+        // from a plain string.
+        let variant = || StrDeserializer::<serde::de::value::Error>::new(name);
+        let binding = match device {
+            "Key" => Binding::Key(KeyCode::deserialize(variant())?),
+            "Mouse" => match name.parse() {
+                Ok(button) => Binding::Mouse(MouseButton::Other(button)),
+                Err(_) => Binding::Mouse(MouseButton::deserialize(variant())?),
+            },
+            "Wheel" if name == "Up" => Binding::WheelUp,
+            "Wheel" if name == "Down" => Binding::WheelDown,
+            "Gamepad" => Binding::Gamepad(GamepadButton::deserialize(variant())?),
+            _ => bail!("unknown input '{}'", s),
+        };
+        Ok(binding)
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// The bindings of every action, as stored in the settings file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self(Action::ALL.iter().map(|&action| (action, action.default_bindings())).collect())
+    }
+}
+
+impl Bindings {
+    /// Gives actions missing from a loaded file their default bindings. An
+    /// empty list is kept, which leaves the action unbound.
+    pub fn fill_defaults(&mut self) {
+        for action in Action::ALL {
+            self.0.entry(action).or_insert_with(|| action.default_bindings());
+        }
+    }
+
+    /// Binds `action` to `binding` alone, taking the binding away from any
+    /// other action.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        for bindings in self.0.values_mut() {
+            bindings.retain(|&b| b != binding);
+        }
+        self.0.insert(action, vec![binding]);
+    }
+
+    fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.0
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(&action, _)| action)
+    }
+}
+
+/// Progress of a rebind started by [`InputMap::begin_rebind`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Rebind {
+    /// Waiting for a binding of the action to change.
+    Choosing,
+    /// Waiting for the new binding of this action.
+    Binding(Action),
+}
+
+/// Turns raw input events into action state. Held actions stay active while
+/// any of their bindings is down; presses and wheel steps accumulate until
+/// taken or until the end of the frame.
+#[derive(Default)]
+pub struct InputMap {
+    bindings: Bindings,
+    down: HashSet<Binding>,
+    presses: HashMap<Action, f32>,
+    rebinding: Option<Rebind>,
+    rebound: bool,
+}
+
+impl InputMap {
+    pub fn new(bindings: Bindings) -> Self {
+        Self { bindings, ..Default::default() }
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+        self.down.clear();
+    }
+
+    /// Starts rebinding an action: the next press of any of its bindings
+    /// picks it, and the key, button or wheel step after that becomes its
+    /// only binding. Pressing an input shared by several actions again moves
+    /// on to the next of them instead. Escape cancels.
+    pub fn begin_rebind(&mut self) {
+        self.rebinding = Some(Rebind::Choosing);
+        log::info!("press an input of the action to rebind, or Escape to cancel");
+    }
+
+    /// Whether bindings changed since the last call, so they can be saved.
+    pub fn take_rebound(&mut self) -> bool {
+        std::mem::take(&mut self.rebound)
+    }
+
+    /// Lets go of every held binding, for when their releases can't be
+    /// seen, as while the window is unfocused.
+    pub fn release_all(&mut self) {
+        self.down.clear();
+    }
+
+    /// Returns whether the event triggered or released any binding.
+    pub fn process_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return false;
+                };
+                if event.repeat {
+                    return self.rebinding.is_none() && self.bindings.actions(Binding::Key(code)).next().is_some();
+                }
+                if self.rebinding.is_some() && code == KeyCode::Escape && event.state == ElementState::Pressed {
+                    self.rebinding = None;
+                    return true;
+                }
+                self.set(Binding::Key(code), event.state == ElementState::Pressed, 1.0)
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set(Binding::Mouse(*button), *state == ElementState::Pressed, 1.0)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 * 0.1,
+                };
+                let binding = if steps > 0.0 { Binding::WheelUp } else { Binding::WheelDown };
+                // The wheel has no release, so a step is a press that is
+                // never held.
+                let bound = self.set(binding, true, steps.abs());
+                self.down.remove(&binding);
+                bound
+            }
+            _ => false,
+        }
+    }
+
+    /// Feeds a gamepad button change.
+    pub fn process_gamepad_button(&mut self, button: GamepadButton, pressed: bool) -> bool {
+        self.set(Binding::Gamepad(button), pressed, 1.0)
+    }
+
+    fn set(&mut self, binding: Binding, pressed: bool, amount: f32) -> bool {
+        if pressed && let Some(rebind) = self.rebinding {
+            match rebind {
+                Rebind::Choosing => {
+                    // Unbound inputs pick nothing and are ignored.
+                    let Some(action) = self.bindings.actions(binding).next() else {
+                        return false;
+                    };
+                    self.choose_rebind(action, binding);
+                }
+                Rebind::Binding(action) if let Some(next) = self.next_sharing(action, binding) => {
+                    self.choose_rebind(next, binding);
+                }
+                Rebind::Binding(action) => {
+                    self.rebinding = None;
+                    self.bindings.rebind(action, binding);
+                    self.rebound = true;
+                    log::info!("bound {:?} to {:?}", action, binding);
+                }
+            }
+            return true;
+        }
+
+        if pressed {
+            self.down.insert(binding);
+        } else {
+            self.down.remove(&binding);
+        }
+        let mut bound = false;
+        for action in self.bindings.actions(binding) {
+            bound = true;
+            if pressed {
+                *self.presses.entry(action).or_default() += amount;
+            }
+        }
+        bound
+    }
+
+    fn choose_rebind(&mut self, action: Action, binding: Binding) {
+        self.rebinding = Some(Rebind::Binding(action));
+        match self.next_sharing(action, binding) {
+            Some(next) => log::info!("press the new input for {:?}, or {} again for {:?}", action, binding, next),
+            None => log::info!("press the new input for {:?}", action),
+        }
+    }
+
+    /// The action after `action` among those bound to `binding`, wrapping
+    /// around, if `binding` is shared with others.
+    fn next_sharing(&self, action: Action, binding: Binding) -> Option<Action> {
+        let sharing: Vec<Action> = self.bindings.actions(binding).collect();
+        let index = sharing.iter().position(|&a| a == action)?;
+        (sharing.len() > 1).then(|| sharing[(index + 1) % sharing.len()])
+    }
+
+    /// Whether any binding of `action` is down.
+    pub fn is_held(&self, action: Action) -> bool {
+        self.down.iter().any(|&binding| self.bindings.actions(binding).any(|a| a == action))
+    }
+
+    /// Consumes the presses of `action` and returns whether there were any.
+    pub fn take_pressed(&mut self, action: Action) -> bool {
+        self.take_amount(action) > 0.0
+    }
+
+    /// Consumes and returns how much `action` was triggered: one per press,
+    /// or the number of wheel steps.
+    pub fn take_amount(&mut self, action: Action) -> f32 {
+        self.presses.remove(&action).unwrap_or(0.0)
+    }
+
+    /// Drops presses nothing took this frame.
+    pub fn end_frame(&mut self) {
+        self.presses.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_picks_an_action_then_its_new_binding() {
+        let mut input = InputMap::new(Bindings::default());
+        input.begin_rebind();
+        assert!(!input.set(Binding::Key(KeyCode::F24), true, 1.0), "unbound inputs pick nothing");
+        assert!(input.set(Binding::Key(KeyCode::KeyG), true, 1.0));
+        input.set(Binding::Key(KeyCode::KeyG), false, 1.0);
+        assert!(input.set(Binding::Gamepad(GamepadButton::East), true, 1.0));
+
+        assert!(input.take_rebound());
+        assert_eq!(input.bindings().0[&Action::ToggleGrid], [Binding::Gamepad(GamepadButton::East)]);
+        // Neither step triggered the grid toggle itself.
+        assert!(!input.take_pressed(Action::ToggleGrid));
+        input.set(Binding::Gamepad(GamepadButton::East), true, 1.0);
+        assert!(input.take_pressed(Action::ToggleGrid));
+    }
+
+    #[test]
+    fn pressing_a_shared_binding_again_picks_the_next_action() {
+        let right = Binding::Mouse(MouseButton::Right);
+        let mut input = InputMap::new(Bindings::default());
+        input.begin_rebind();
+        input.set(right, true, 1.0);
+        assert_eq!(input.rebinding, Some(Rebind::Binding(Action::RotateDrag)));
+        input.set(right, false, 1.0);
+        input.set(right, true, 1.0);
+        assert_eq!(input.rebinding, Some(Rebind::Binding(Action::OpenMenu)));
+        input.set(right, false, 1.0);
+        input.set(Binding::Key(KeyCode::KeyM), true, 1.0);
+
+        assert_eq!(input.bindings().0[&Action::OpenMenu], [Binding::Key(KeyCode::KeyM)]);
+        assert_eq!(input.bindings().actions(right).collect::<Vec<_>>(), [Action::RotateDrag]);
+    }
+
+    #[test]
+    fn rebinding_takes_the_binding_from_other_actions() {
+        let mut bindings = Bindings::default();
+        bindings.rebind(Action::ToggleGrid, Binding::Key(KeyCode::KeyH));
+        assert_eq!(bindings.0[&Action::ToggleGrid], [Binding::Key(KeyCode::KeyH)]);
+        assert!(bindings.0[&Action::ToggleTileHighlights].is_empty());
+    }
+
+    #[test]
+    fn default_bindings_are_unique() {
+        // Except for the right mouse button, which opens the menu on a click
+        // and turns the camera on a drag.
+        let right = Binding::Mouse(MouseButton::Right);
+        let bindings = Bindings::default();
+        assert_eq!(bindings.actions(right).collect::<Vec<_>>(), [Action::RotateDrag, Action::OpenMenu]);
+
+        let mut seen = HashSet::new();
+        for &binding in bindings.0.values().flatten().filter(|&&b| b != right) {
+            assert!(seen.insert(binding), "{} is bound twice", binding);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::assets::{AssetManager, Handle};
+use crate::culling::{CullStats, Frustum};
+use crate::model::{InstanceRaw, Model};
+use glam::Mat4;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Frames whose instance data may still be read by the GPU. Each frame writes
+/// to its own segment of the buffer, round-robin.
+const FRAMES_IN_FLIGHT: u64 = 3;
+const INITIAL_CAPACITY: usize = 256;
+const STRIDE: u64 = std::mem::size_of::<InstanceRaw>() as u64;
+
+/// The per-instance vertex data of every draw in a frame, in one buffer that
+/// is reused across frames and only reallocated when a frame needs more room.
+pub struct InstanceArena {
+    buffer: wgpu::Buffer,
+    /// Instances that fit in one frame's segment.
+    capacity: usize,
+    frame: u64,
+    instances: Vec<InstanceRaw>,
+}
+
+impl InstanceArena {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            frame: 0,
+            instances: Vec::with_capacity(INITIAL_CAPACITY),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Arena"),
+            size: capacity as u64 * STRIDE * FRAMES_IN_FLIGHT,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Moves on to the next segment and forgets the previous frame's instances.
+    pub fn begin_frame(&mut self) {
+        self.instances.clear();
+        self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
+    }
+
+    /// Adds instances to this frame and returns their range for `draw_indexed`.
+    pub fn push(&mut self, transforms: impl IntoIterator<Item = Mat4>) -> Range<u32> {
+        let start = self.instances.len() as u32;
+        self.instances
+            .extend(transforms.into_iter().map(|transform| InstanceRaw { model: transform.to_cols_array_2d() }));
+        start..self.instances.len() as u32
+    }
+
+    /// Copies this frame's instances to the GPU, growing the buffer first if
+    /// they don't fit.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+            log::debug!("instance arena grown to {} instances per frame", self.capacity);
+        }
+        if !self.instances.is_empty() {
+            queue.write_buffer(&self.buffer, self.segment_offset(), bytemuck::cast_slice(&self.instances));
+        }
+    }
+
+    /// This frame's segment, to bind at vertex slot 1.
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        let offset = self.segment_offset();
+        self.buffer.slice(offset..offset + self.capacity as u64 * STRIDE)
+    }
+
+    fn segment_offset(&self) -> u64 {
+        self.frame * self.capacity as u64 * STRIDE
+    }
+}
+
+/// Every visible instance of one model at one detail level, drawn with a
+/// single instanced call per mesh.
+pub struct Batch {
+    pub model: Handle<Model>,
+    pub meshes: Vec<usize>,
+    pub instances: Range<u32>,
+}
+
+/// Groups `(model, transform, detail level)` entities into batches, drops
+/// instances and meshes outside the frustum and pushes the remaining
+/// transforms into `arena`. Counts in `stats` are per mesh instance.
+pub fn batch_entities(
+    entities: &[(Handle<Model>, Mat4, usize)],
+    assets: &AssetManager,
+    frustum: &Frustum,
+    stats: &mut CullStats,
+    arena: &mut InstanceArena,
+) -> Vec<Batch> {
+    // Groups keep the order in which their first entity appears, so draw
+    // order doesn't change from frame to frame.
+    let mut keys = Vec::new();
+    let mut groups: Vec<Vec<Mat4>> = Vec::new();
+    let mut group_index = HashMap::new();
+    for &(model, transform, lod) in entities {
+        let index = *group_index.entry((model, lod)).or_insert_with(|| {
+            keys.push((model, lod));
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(transform);
+    }
+
+    let mut batches = Vec::with_capacity(groups.len());
+    for ((handle, lod), transforms) in keys.into_iter().zip(groups) {
+        let Some(model) = assets.model(handle) else {
+            continue;
+        };
+        let lod_meshes: Vec<usize> = (0..model.meshes.len()).filter(|&i| model.meshes[i].lod == lod).collect();
+        let visible: Vec<Mat4> = transforms
+            .iter()
+            .copied()
+            .filter(|transform| frustum.intersects(&model.bounds.transformed(transform)))
+            .collect();
+        stats.add(false, (transforms.len() - visible.len()) * lod_meshes.len());
+        if visible.is_empty() {
+            continue;
+        }
+
+        let meshes: Vec<usize> = lod_meshes
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let bounds = model.meshes[i].bounds;
+                visible.iter().any(|transform| frustum.intersects(&bounds.transformed(transform)))
+            })
+            .collect();
+        stats.add(true, meshes.len() * visible.len());
+        stats.add(false, (lod_meshes.len() - meshes.len()) * visible.len());
+
+        let instances = arena.push(visible);
+        batches.push(Batch { model: handle, meshes, instances });
+    }
+    batches
+}
@@ -0,0 +1,302 @@
+use crate::block_compression::{self, BlockFormat};
+use crate::model::texture::ImageData;
+use anyhow::{anyhow, bail, Context, Result};
+use ktx2::{Format, SupercompressionScheme};
+use std::io::Read;
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+const MAGIC: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Reads a 2D KTX2 texture. Block-compressed data is kept as it is when
+/// `features` let the device sample it and the file has its own mip chain,
+/// and is decoded to RGBA8 on the CPU otherwise. Zstandard and zlib
+/// supercompression are undone here.
+pub fn decode(bytes: &[u8], features: wgpu::Features) -> Result<ImageData> {
+    let reader = ktx2::Reader::new(bytes).context("invalid KTX2 file")?;
+    let header = reader.header();
+    if header.supercompression_scheme == Some(SupercompressionScheme::BasisLZ) {
+        bail!("Basis Universal ETC1S textures need transcoding, which isn't supported; encode as BC7 or ETC2 instead");
+    }
+    let Some(format) = header.format else {
+        bail!("KTX2 file has no concrete format (Basis Universal UASTC?); encode as BC7 or ETC2 instead");
+    };
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        bail!("only 2D KTX2 textures are supported, not arrays, cube maps or volumes");
+    }
+
+    let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+    let levels = reader
+        .levels()
+        .map(|level| inflate(level.data, header.supercompression_scheme))
+        .collect::<Result<Vec<_>>>()?;
+    decode_levels(format, width, height, levels, features)
+}
+
+fn decode_levels(
+    format: Format,
+    width: u32,
+    height: u32,
+    levels: Vec<Vec<u8>>,
+    features: wgpu::Features,
+) -> Result<ImageData> {
+    let target = texture_format(format).ok_or_else(|| anyhow!("unsupported KTX2 format {:?}", format))?;
+    let (block_width, block_height) = target.block_dimensions();
+    let sampleable = features.contains(target.required_features())
+        && width.is_multiple_of(block_width)
+        && height.is_multiple_of(block_height);
+    // Block-compressed mips can't be generated here, so a lone compressed
+    // level is decoded to build the chain from.
+    let has_mips = levels.len() > 1 || !target.is_compressed();
+    if sampleable && !has_mips {
+        log::warn!("{:?} texture has no mip chain and is decoded to build one; export it with mips to keep it compressed", format);
+    }
+    if sampleable && has_mips {
+        for (i, data) in levels.iter().enumerate() {
+            let expected = level_size(target, width >> i, height >> i);
+            if data.len() != expected {
+                bail!("level {} of {:?} texture has {} bytes, expected {}", i, format, data.len(), expected);
+            }
+        }
+        let mut image = ImageData { width, height, format: target, mips: levels };
+        if !target.is_compressed() && image.mips.len() == 1 {
+            image.generate_mips();
+        }
+        return Ok(image);
+    }
+
+    let decoder = cpu_decoder(format, target).ok_or_else(|| {
+        anyhow!("{:?} textures aren't supported by this GPU and can't be decoded on the CPU", format)
+    })?;
+    let mips = levels
+        .iter()
+        .enumerate()
+        .map(|(i, data)| decoder.decode(data, (width >> i).max(1), (height >> i).max(1)))
+        .collect::<Result<Vec<_>>>()?;
+    log::debug!("decoded {:?} texture on the CPU", format);
+
+    let level_count = mips.len();
+    let mut image = ImageData { width, height, format: rgba8(target.is_srgb()), mips };
+    if level_count == 1 {
+        image.generate_mips();
+    }
+    Ok(image)
+}
+
+fn inflate(data: &[u8], scheme: Option<SupercompressionScheme>) -> Result<Vec<u8>> {
+    let mut inflated = Vec::new();
+    match scheme {
+        None => inflated.extend_from_slice(data),
+        Some(SupercompressionScheme::Zstandard) => {
+            ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|e| anyhow!("invalid Zstandard level: {}", e))?
+                .read_to_end(&mut inflated)?;
+        }
+        Some(SupercompressionScheme::ZLIB) => {
+            flate2::read::ZlibDecoder::new(data).read_to_end(&mut inflated)?;
+        }
+        Some(other) => bail!("unsupported KTX2 supercompression {:?}", other),
+    }
+    Ok(inflated)
+}
+
+fn level_size(format: TextureFormat, width: u32, height: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let blocks_x = width.max(1).div_ceil(block_width);
+    let blocks_y = height.max(1).div_ceil(block_height);
+    (blocks_x * blocks_y * format.block_copy_size(None).unwrap_or(0)) as usize
+}
+
+fn rgba8(srgb: bool) -> TextureFormat {
+    if srgb {
+        TextureFormat::Rgba8UnormSrgb
+    } else {
+        TextureFormat::Rgba8Unorm
+    }
+}
+
+/// The wgpu format matching a KTX2 format, if the renderer can use it.
+fn texture_format(format: Format) -> Option<TextureFormat> {
+    let astc = |block, srgb| TextureFormat::Astc {
+        block,
+        channel: if srgb { AstcChannel::UnormSrgb } else { AstcChannel::Unorm },
+    };
+    Some(match format {
+        Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        Format::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        Format::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        Format::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+        Format::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+        Format::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+        Format::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+        Format::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, false),
+        Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, true),
+        Format::ASTC_5x4_UNORM_BLOCK => astc(AstcBlock::B5x4, false),
+        Format::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4, true),
+        Format::ASTC_5x5_UNORM_BLOCK => astc(AstcBlock::B5x5, false),
+        Format::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5, true),
+        Format::ASTC_6x5_UNORM_BLOCK => astc(AstcBlock::B6x5, false),
+        Format::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5, true),
+        Format::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, false),
+        Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, true),
+        Format::ASTC_8x5_UNORM_BLOCK => astc(AstcBlock::B8x5, false),
+        Format::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5, true),
+        Format::ASTC_8x6_UNORM_BLOCK => astc(AstcBlock::B8x6, false),
+        Format::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6, true),
+        Format::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, false),
+        Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, true),
+        Format::ASTC_10x5_UNORM_BLOCK => astc(AstcBlock::B10x5, false),
+        Format::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5, true),
+        Format::ASTC_10x6_UNORM_BLOCK => astc(AstcBlock::B10x6, false),
+        Format::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6, true),
+        Format::ASTC_10x8_UNORM_BLOCK => astc(AstcBlock::B10x8, false),
+        Format::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8, true),
+        Format::ASTC_10x10_UNORM_BLOCK => astc(AstcBlock::B10x10, false),
+        Format::ASTC_10x10_SRGB_BLOCK => astc(AstcBlock::B10x10, true),
+        Format::ASTC_12x10_UNORM_BLOCK => astc(AstcBlock::B12x10, false),
+        Format::ASTC_12x10_SRGB_BLOCK => astc(AstcBlock::B12x10, true),
+        Format::ASTC_12x12_UNORM_BLOCK => astc(AstcBlock::B12x12, false),
+        Format::ASTC_12x12_SRGB_BLOCK => astc(AstcBlock::B12x12, true),
+        _ => return None,
+    })
+}
+
+/// A software decoder for a block-compressed format.
+#[derive(Debug, Clone, Copy)]
+enum CpuDecoder {
+    Blocks(BlockFormat),
+    Astc { block_width: u32, block_height: u32 },
+    /// HDR colours are clamped to the 0-1 range of RGBA8.
+    Bc6h { signed: bool },
+}
+
+impl CpuDecoder {
+    /// Decodes one mip level into tightly packed RGBA8 pixels.
+    fn decode(self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        let (w, h) = (width as usize, height as usize);
+        let mut pixels = vec![0u32; w * h];
+        match self {
+            CpuDecoder::Blocks(format) => return block_compression::decode(format, data, width, height),
+            CpuDecoder::Astc { block_width, block_height } => {
+                texture2ddecoder::decode_astc(data, w, h, block_width as usize, block_height as usize, &mut pixels)
+            }
+            CpuDecoder::Bc6h { signed } => texture2ddecoder::decode_bc6(data, w, h, &mut pixels, signed),
+        }
+        .map_err(|e| anyhow!("{:?}: {}", self, e))?;
+        // texture2ddecoder packs each pixel as BGRA in a little-endian word.
+        Ok(pixels
+            .iter()
+            .flat_map(|pixel| {
+                let [b, g, r, a] = pixel.to_le_bytes();
+                [r, g, b, a]
+            })
+            .collect())
+    }
+}
+
+/// The software decoder for formats the GPU may lack. `target` is the wgpu
+/// format the file would otherwise be sampled as.
+fn cpu_decoder(format: Format, target: TextureFormat) -> Option<CpuDecoder> {
+    if let TextureFormat::Astc { .. } = target {
+        let (block_width, block_height) = target.block_dimensions();
+        return Some(CpuDecoder::Astc { block_width, block_height });
+    }
+    Some(CpuDecoder::Blocks(match format {
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK => BlockFormat::Bc1,
+        Format::BC2_UNORM_BLOCK | Format::BC2_SRGB_BLOCK => BlockFormat::Bc2,
+        Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => BlockFormat::Bc3,
+        Format::BC4_UNORM_BLOCK => BlockFormat::Bc4 { signed: false },
+        Format::BC4_SNORM_BLOCK => BlockFormat::Bc4 { signed: true },
+        Format::BC5_UNORM_BLOCK => BlockFormat::Bc5 { signed: false },
+        Format::BC5_SNORM_BLOCK => BlockFormat::Bc5 { signed: true },
+        Format::BC7_UNORM_BLOCK | Format::BC7_SRGB_BLOCK => BlockFormat::Bc7,
+        Format::ETC2_R8G8B8_UNORM_BLOCK | Format::ETC2_R8G8B8_SRGB_BLOCK => BlockFormat::Etc2Rgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK | Format::ETC2_R8G8B8A1_SRGB_BLOCK => BlockFormat::Etc2RgbA1,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK | Format::ETC2_R8G8B8A8_SRGB_BLOCK => BlockFormat::Etc2Rgba,
+        Format::EAC_R11_UNORM_BLOCK => BlockFormat::EacR11 { signed: false },
+        Format::EAC_R11_SNORM_BLOCK => BlockFormat::EacR11 { signed: true },
+        Format::EAC_R11G11_UNORM_BLOCK => BlockFormat::EacRg11 { signed: false },
+        Format::EAC_R11G11_SNORM_BLOCK => BlockFormat::EacRg11 { signed: true },
+        Format::BC6H_UFLOAT_BLOCK => return Some(CpuDecoder::Bc6h { signed: false }),
+        Format::BC6H_SFLOAT_BLOCK => return Some(CpuDecoder::Bc6h { signed: true }),
+        _ => return None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ASTC void-extent block: one constant colour, here as UNORM16
+    /// channels of which the decoder keeps the high bytes.
+    fn astc_constant_block(rgba: [u8; 4]) -> Vec<u8> {
+        let mut block = vec![0xFC, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        for channel in rgba {
+            block.extend_from_slice(&[0, channel]);
+        }
+        block
+    }
+
+    #[test]
+    fn astc_is_decoded_on_the_cpu_without_the_feature() {
+        let block = astc_constant_block([10, 20, 30, 255]);
+        let image = decode_levels(Format::ASTC_4x4_SRGB_BLOCK, 4, 4, vec![block], wgpu::Features::empty()).unwrap();
+        assert_eq!(image.format, TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(image.mips[0], [10, 20, 30, 255].repeat(16));
+    }
+
+    #[test]
+    fn astc_blocks_larger_than_the_image_are_cropped() {
+        let block = astc_constant_block([1, 2, 3, 4]);
+        let image = decode_levels(Format::ASTC_6x6_UNORM_BLOCK, 5, 3, vec![block], wgpu::Features::empty()).unwrap();
+        assert_eq!(image.mips[0], [1, 2, 3, 4].repeat(15));
+    }
+
+    #[test]
+    fn bc6h_is_decoded_on_the_cpu_without_the_feature() {
+        // Mode 0 with every endpoint zero is black.
+        let image = decode_levels(Format::BC6H_UFLOAT_BLOCK, 4, 4, vec![vec![0; 16]], wgpu::Features::empty()).unwrap();
+        assert_eq!(image.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(image.mips[0], [0, 0, 0, 255].repeat(16));
+    }
+
+    #[test]
+    fn compressed_mip_chains_are_kept_when_the_gpu_samples_them() {
+        let levels = vec![vec![0; 32], vec![0; 8], vec![0; 8]];
+        let image = decode_levels(Format::BC1_RGBA_UNORM_BLOCK, 8, 8, levels.clone(), wgpu::Features::TEXTURE_COMPRESSION_BC).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnorm);
+        assert_eq!(image.mips, levels);
+    }
+
+    #[test]
+    fn a_lone_compressed_level_is_decoded_to_build_mips() {
+        let image = decode_levels(Format::BC1_RGBA_UNORM_BLOCK, 8, 8, vec![vec![0; 32]], wgpu::Features::TEXTURE_COMPRESSION_BC).unwrap();
+        assert_eq!(image.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(image.mips.iter().map(Vec::len).collect::<Vec<_>>(), [256, 64, 16, 4]);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::assets::{Handle, Texture};
+use crate::model::{self, texture, GltfError, Model, ModelData};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+pub enum Job {
+    Model(Handle<Model>, PathBuf),
+    Texture(Handle<Texture>, PathBuf),
+}
+
+/// The CPU half of a load, waiting for the main thread to upload it.
+pub enum Loaded {
+    Model(Handle<Model>, PathBuf, Result<ModelData, GltfError>),
+    Texture(Handle<Texture>, PathBuf, anyhow::Result<texture::ImageData>),
+}
+
+/// A small pool of worker threads that parse and decode assets. Results are
+/// collected with [`Loader::try_recv`] on the thread that owns the device.
+pub struct Loader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Loaded>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Loader {
+    /// `features` are the device's, deciding which compressed texture formats
+    /// can be kept as they are.
+    pub fn new(features: wgpu::Features) -> Self {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1)
+            .clamp(1, 4);
+
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads)
+            .map(|i| {
+                let jobs = Arc::clone(&job_receiver);
+                let results = result_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset-loader-{}", i))
+                    .spawn(move || worker(jobs, results, features))
+                    .expect("failed to spawn asset loader thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results,
+            workers,
+        }
+    }
+
+    pub fn submit(&self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Loaded> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(jobs: Arc<Mutex<Receiver<Job>>>, results: Sender<Loaded>, features: wgpu::Features) {
+    loop {
+        let job = match jobs.lock() {
+            Ok(jobs) => jobs.recv(),
+            Err(_) => return,
+        };
+        let loaded = match job {
+            Ok(Job::Model(handle, path)) => {
+                let data = model::decode_gltf(&path, features);
+                Loaded::Model(handle, path, data)
+            }
+            Ok(Job::Texture(handle, path)) => {
+                let image = std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| texture::decode(&bytes, features));
+                Loaded::Texture(handle, path, image)
+            }
+            Err(_) => return,
+        };
+        if results.send(loaded).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::culling::Aabb;
+use glam::Vec3;
+
+/// How far past a threshold the screen size has to move before the level
+/// changes, as a fraction of the threshold. Keeps objects sitting on a
+/// boundary from flickering between levels.
+const HYSTERESIS: f32 = 0.15;
+
+/// Screen sizes below which a model drops to its next coarser level.
+pub const MODEL_LOD_THRESHOLDS: [f32; 3] = [0.1, 0.05, 0.025];
+
+/// Fraction of the viewport height covered by the sphere around `bounds` when
+/// seen from `eye` with a vertical field of view of `fovy` radians.
+pub fn screen_size(bounds: &Aabb, eye: Vec3, fovy: f32) -> f32 {
+    let radius = (bounds.max - bounds.min).length() * 0.5;
+    let distance = bounds.center().distance(eye).max(f32::EPSILON);
+    radius / (distance * (fovy * 0.5).tan())
+}
+
+/// The detail level currently used for one object.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Lod {
+    pub level: usize,
+}
+
+impl Lod {
+    /// Moves towards the level `screen_size` calls for and returns it.
+    /// `thresholds` are descending screen sizes: below `thresholds[i]` the
+    /// object drops to level `i + 1`. Never goes past `max_level`.
+    pub fn update(&mut self, screen_size: f32, thresholds: &[f32], max_level: usize) -> usize {
+        let max_level = max_level.min(thresholds.len());
+        self.level = self.level.min(max_level);
+        while self.level < max_level && screen_size < thresholds[self.level] * (1.0 - HYSTERESIS) {
+            self.level += 1;
+        }
+        while self.level > 0 && screen_size > thresholds[self.level - 1] * (1.0 + HYSTERESIS) {
+            self.level -= 1;
+        }
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_change_only_past_the_hysteresis_band() {
+        let mut lod = Lod::default();
+        // Just below the first threshold, but inside the band.
+        assert_eq!(lod.update(0.09, &MODEL_LOD_THRESHOLDS, 3), 0);
+        assert_eq!(lod.update(0.08, &MODEL_LOD_THRESHOLDS, 3), 1);
+        // Back just above it, still inside the band.
+        assert_eq!(lod.update(0.11, &MODEL_LOD_THRESHOLDS, 3), 1);
+        assert_eq!(lod.update(0.12, &MODEL_LOD_THRESHOLDS, 3), 0);
+    }
+
+    #[test]
+    fn large_jumps_skip_levels() {
+        let mut lod = Lod::default();
+        assert_eq!(lod.update(0.001, &MODEL_LOD_THRESHOLDS, 3), 3);
+        assert_eq!(lod.update(1.0, &MODEL_LOD_THRESHOLDS, 3), 0);
+    }
+
+    #[test]
+    fn levels_stop_at_the_coarsest_available() {
+        let mut lod = Lod::default();
+        assert_eq!(lod.update(0.001, &MODEL_LOD_THRESHOLDS, 1), 1);
+        // A model that lost its coarser levels on reload drops back to them.
+        let mut lod = Lod { level: 3 };
+        assert_eq!(lod.update(0.001, &MODEL_LOD_THRESHOLDS, 2), 2);
+        assert_eq!(lod.update(0.001, &[], 5), 0);
+    }
+
+    #[test]
+    fn screen_size_halves_with_twice_the_distance() {
+        let bounds = Aabb { min: Vec3::splat(-1.0), max: Vec3::splat(1.0) };
+        let fovy = std::f32::consts::FRAC_PI_2;
+        let near = screen_size(&bounds, Vec3::new(0.0, 0.0, 10.0), fovy);
+        let far = screen_size(&bounds, Vec3::new(0.0, 0.0, 20.0), fovy);
+        assert!((near - 3f32.sqrt() / 10.0).abs() < 1e-5);
+        assert!((near - far * 2.0).abs() < 1e-5);
+    }
+}
@@ -1,25 +1,45 @@
 mod renderer;
+mod assets;
+mod loader;
+#[cfg(debug_assertions)]
+mod hot_reload;
 mod camera;
 mod camera_controller;
+mod context_menu;
+mod culling;
+mod entity;
+mod gamepad;
+mod input;
+mod instances;
+mod lod;
+mod overlay;
 mod player;
+mod post_process;
+mod render_graph;
+mod settings;
+mod sky;
 mod model;
+mod ktx;
+mod block_compression;
 mod world;
+mod water;
+mod terrain;
 
+use input::Action;
 use renderer::State;
 use std::sync::Arc;
 use winit::{
     application::ApplicationHandler,
     event::*,
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{Key, NamedKey},
-    window::{Window, WindowId},
+    window::{CursorGrabMode, Window, WindowId},
 };
 
 #[derive(Default)]
 struct App {
     window: Option<Arc<Window>>,
     state: Option<State>,
-    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    cursor_grabbed: bool,
 }
 
 impl ApplicationHandler for App {
@@ -45,10 +65,10 @@ impl ApplicationHandler for App {
         _device_id: DeviceId,
         event: DeviceEvent,
     ) {
-        if let Some(state) = self.state.as_mut() {
-            if let DeviceEvent::MouseMotion { delta } = event {
-                state.mouse_motion(delta);
-            }
+        if let Some(state) = self.state.as_mut()
+            && let DeviceEvent::MouseMotion { delta } = event
+        {
+            state.mouse_motion(delta);
         }
     }
 
@@ -66,50 +86,52 @@ impl ApplicationHandler for App {
             return;
         }
 
-        if !state.input(&event) {
-            match event {
-                WindowEvent::CursorMoved { position, .. } => {
-                    self.cursor_position = position;
-                }
-                WindowEvent::MouseInput {
-                    state: ElementState::Pressed,
-                    button: MouseButton::Left,
-                    ..
-                } => {
-                    state.set_player_destination(self.cursor_position);
-                }
-                WindowEvent::CloseRequested
-                | WindowEvent::KeyboardInput {
-                    event:
-                        KeyEvent {
-                            state: ElementState::Pressed,
-                            logical_key: Key::Named(NamedKey::Escape),
-                            ..
-                        },
-                    ..
-                } => {
-                    event_loop.exit();
-                }
-                WindowEvent::Resized(physical_size) => {
-                    state.resize(physical_size);
-                    window.request_redraw();
-                }
-                WindowEvent::RedrawRequested => {
-                    match state.render() {
-                        Ok(_) => {}
-                        Err(wgpu::SurfaceError::Lost) => state.resize(state.size()),
-                        Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
-                        Err(e) => eprintln!("{:?}", e),
-                    }
+        let bound = state.input(&event);
+        if state.take_action(Action::Quit) {
+            event_loop.exit();
+            return;
+        }
+        if bound {
+            return;
+        }
+
+        match event {
+            WindowEvent::CloseRequested => {
+                event_loop.exit();
+            }
+            WindowEvent::Resized(physical_size) => {
+                state.resize(physical_size);
+                window.request_redraw();
+            }
+            WindowEvent::RedrawRequested => {
+                match state.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size()),
+                    Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
+                    Err(e) => eprintln!("{:?}", e),
                 }
-                _ => {}
             }
+            _ => {}
         }
     }
 
     fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
         if let Some(state) = self.state.as_mut() {
             state.update();
+            if let Some(position) = state.take_cursor_warp()
+                && let Some(window) = self.window.as_ref()
+            {
+                // Not every platform lets the cursor be moved; the virtual
+                // cursor still clicks where it is without it.
+                let _ = window.set_cursor_position(position);
+            }
+            let grab = state.wants_cursor_grab();
+            if grab != self.cursor_grabbed
+                && let Some(window) = self.window.as_ref()
+            {
+                grab_cursor(window, grab);
+                self.cursor_grabbed = grab;
+            }
         }
         if let Some(window) = self.window.as_ref() {
             window.request_redraw();
@@ -117,6 +139,22 @@ impl ApplicationHandler for App {
     }
 }
 
+/// Locks and hides the cursor, or frees and shows it. Platforms that can't
+/// lock the cursor in place confine it to the window instead.
+fn grab_cursor(window: &Window, grab: bool) {
+    let result = if grab {
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(e) = result {
+        log::warn!("failed to {} the cursor: {}", if grab { "grab" } else { "release" }, e);
+    }
+    window.set_cursor_visible(!grab);
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     let event_loop = EventLoop::new()?;
@@ -1,42 +1,225 @@
 use anyhow::Result;
-use std::path::Path;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use wgpu::util::DeviceExt;
-use crate::world::WORLD_SIZE;
-use crate::world::World;
+use crate::culling::Aabb;
+use crate::renderer::MaterialPipelines;
+use crate::assets::{AssetManager, Handle, MaterialSlots, TextureSlot};
 
 pub mod texture {
     use super::*;
     use image::GenericImageView;
     use wgpu::util::DeviceExt;
 
-    pub fn from_bytes(
+    /// Pixels ready to upload: RGBA8, or block-compressed data the device can
+    /// sample directly. Produced off the main thread by the asset loader.
+    pub struct ImageData {
+        pub width: u32,
+        pub height: u32,
+        /// Colour textures (base colour, emissive) use an sRGB format; data
+        /// textures such as normal or metallic-roughness maps are linear.
+        pub format: wgpu::TextureFormat,
+        /// The full-size image followed by each smaller mip level.
+        pub mips: Vec<Vec<u8>>,
+    }
+
+    impl ImageData {
+        /// A single-level image; call [`ImageData::generate_mips`] to fill in
+        /// the rest of the chain.
+        pub fn new(width: u32, height: u32, rgba: Vec<u8>, srgb: bool) -> Self {
+            let format = if srgb {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            } else {
+                wgpu::TextureFormat::Rgba8Unorm
+            };
+            Self { width, height, format, mips: vec![rgba] }
+        }
+
+        /// Converts the already-decoded pixels the `gltf` crate returns, in any
+        /// of its channel layouts and bit depths, to RGBA8 with mips. One- and
+        /// two-channel images are greyscale and greyscale-alpha, as produced by
+        /// the PNG decoder.
+        pub fn from_gltf(image: &gltf::image::Data, srgb: bool) -> Result<Self> {
+            use gltf::image::Format;
+
+            let (channels, bytes_per_channel) = match image.format {
+                Format::R8 => (1, 1),
+                Format::R8G8 => (2, 1),
+                Format::R8G8B8 => (3, 1),
+                Format::R8G8B8A8 => (4, 1),
+                Format::R16 => (1, 2),
+                Format::R16G16 => (2, 2),
+                Format::R16G16B16 => (3, 2),
+                Format::R16G16B16A16 => (4, 2),
+                Format::R32G32B32FLOAT => (3, 4),
+                Format::R32G32B32A32FLOAT => (4, 4),
+            };
+            let pixel_count = (image.width * image.height) as usize;
+            let expected = pixel_count * channels * bytes_per_channel;
+            if image.pixels.len() != expected {
+                anyhow::bail!(
+                    "{:?} image of {}x{} has {} bytes, expected {}",
+                    image.format,
+                    image.width,
+                    image.height,
+                    image.pixels.len(),
+                    expected
+                );
+            }
+
+            // Float images hold linear values and need encoding for sRGB
+            // slots; integer images are already stored in the slot's space.
+            let to_u8 = |bytes: &[u8]| match bytes_per_channel {
+                1 => bytes[0],
+                2 => {
+                    let v = u16::from_ne_bytes([bytes[0], bytes[1]]) as u32;
+                    ((v * 255 + 32767) / 65535) as u8
+                }
+                _ => {
+                    let v = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).clamp(0.0, 1.0);
+                    let encoded = if srgb { linear_to_srgb(v) } else { v };
+                    (encoded * 255.0).round() as u8
+                }
+            };
+            let alpha_to_u8 = |bytes: &[u8]| match bytes_per_channel {
+                4 => (f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).clamp(0.0, 1.0) * 255.0)
+                    .round() as u8,
+                _ => to_u8(bytes),
+            };
+
+            let mut rgba = Vec::with_capacity(pixel_count * 4);
+            for pixel in image.pixels.chunks_exact(channels * bytes_per_channel) {
+                let channel = |i: usize| &pixel[i * bytes_per_channel..(i + 1) * bytes_per_channel];
+                let texel = match channels {
+                    1 => {
+                        let l = to_u8(channel(0));
+                        [l, l, l, 255]
+                    }
+                    2 => {
+                        let l = to_u8(channel(0));
+                        [l, l, l, alpha_to_u8(channel(1))]
+                    }
+                    3 => [to_u8(channel(0)), to_u8(channel(1)), to_u8(channel(2)), 255],
+                    _ => [
+                        to_u8(channel(0)),
+                        to_u8(channel(1)),
+                        to_u8(channel(2)),
+                        alpha_to_u8(channel(3)),
+                    ],
+                };
+                rgba.extend_from_slice(&texel);
+            }
+
+            let mut data = Self::new(image.width, image.height, rgba, srgb);
+            data.generate_mips();
+            Ok(data)
+        }
+
+        /// Replaces any existing mip levels with a full chain down to 1x1, each
+        /// level a 2x2 box filter of the previous one. sRGB colour channels are
+        /// averaged in linear space so distant textures don't darken. Only
+        /// valid for RGBA8 images.
+        pub fn generate_mips(&mut self) {
+            debug_assert!(!self.format.is_compressed());
+            self.mips.truncate(1);
+            let (mut width, mut height) = (self.width, self.height);
+            while width > 1 || height > 1 {
+                let next = downsample(&self.mips[self.mips.len() - 1], width, height, self.format.is_srgb());
+                width = (width / 2).max(1);
+                height = (height / 2).max(1);
+                self.mips.push(next);
+            }
+        }
+    }
+
+    fn srgb_to_linear(c: f32) -> f32 {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    fn linear_to_srgb(c: f32) -> f32 {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    fn downsample(src: &[u8], width: u32, height: u32, srgb: bool) -> Vec<u8> {
+        let (dst_width, dst_height) = ((width / 2).max(1), (height / 2).max(1));
+        let to_linear = |v: u8| {
+            let c = v as f32 / 255.0;
+            if srgb { srgb_to_linear(c) } else { c }
+        };
+        let from_linear = |c: f32| {
+            let c = if srgb { linear_to_srgb(c) } else { c };
+            (c.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+
+        let mut dst = Vec::with_capacity((dst_width * dst_height * 4) as usize);
+        for y in 0..dst_height {
+            for x in 0..dst_width {
+                let mut sum = [0.0f32; 4];
+                for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let px = (x * 2 + sx).min(width - 1);
+                    let py = (y * 2 + sy).min(height - 1);
+                    let i = ((py * width + px) * 4) as usize;
+                    for c in 0..3 {
+                        sum[c] += to_linear(src[i + c]);
+                    }
+                    sum[3] += src[i + 3] as f32 / 255.0;
+                }
+                dst.extend(sum[..3].iter().map(|c| from_linear(c / 4.0)));
+                dst.push(((sum[3] / 4.0) * 255.0).round() as u8);
+            }
+        }
+        dst
+    }
+
+    /// Decodes an encoded image file (PNG, JPEG, ...) into an sRGB colour
+    /// texture. KTX2 files keep their own format and colour space, and stay
+    /// block-compressed when `features` allow it.
+    pub fn decode(bytes: &[u8], features: wgpu::Features) -> Result<ImageData> {
+        if crate::ktx::is_ktx2(bytes) {
+            return crate::ktx::decode(bytes, features);
+        }
+        let img = image::load_from_memory(bytes)?;
+        let (width, height) = img.dimensions();
+        let mut image = ImageData::new(width, height, img.to_rgba8().into_raw(), true);
+        image.generate_mips();
+        Ok(image)
+    }
+
+    /// Creates the texture with its full mip chain. Only the view is
+    /// returned, which keeps the texture alive.
+    pub fn upload(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8],
+        image: &ImageData,
         label: &str,
-    ) -> Result<(wgpu::Texture, wgpu::TextureView, wgpu::Sampler)> {
-        let img = image::load_from_memory(bytes)?;
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
-
+    ) -> (wgpu::TextureView, wgpu::Sampler) {
         let texture = device.create_texture_with_data(
             queue,
             &wgpu::TextureDescriptor {
                 label: Some(label),
                 size: wgpu::Extent3d {
-                    width: dimensions.0,
-                    height: dimensions.1,
+                    width: image.width,
+                    height: image.height,
                     depth_or_array_layers: 1,
                 },
-                mip_level_count: 1,
+                mip_level_count: image.mips.len() as u32,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: image.format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             },
             wgpu::util::TextureDataOrder::LayerMajor,
-            &rgba,
+            &image.mips.concat(),
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -50,7 +233,74 @@ pub mod texture {
             ..Default::default()
         });
 
-        Ok((texture, view, sampler))
+        (view, sampler)
+    }
+
+    /// A 1x1 texture of a single colour, used for material slots the asset
+    /// doesn't provide.
+    pub fn solid_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: [u8; 4],
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> wgpu::TextureView {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &rgba,
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Translates a glTF sampler into a wgpu sampler description. Missing filters
+    /// default to trilinear filtering, as the glTF spec leaves them up to the
+    /// implementation.
+    pub fn sampler_from_gltf(sampler: &gltf::texture::Sampler) -> wgpu::SamplerDescriptor<'static> {
+        use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+        let address_mode = |mode| match mode {
+            WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+        };
+        let mag_filter = match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+        };
+        let (min_filter, mipmap_filter, use_mips) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, false),
+            Some(MinFilter::Linear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, false),
+            Some(MinFilter::NearestMipmapNearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, true),
+            Some(MinFilter::LinearMipmapNearest) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, true),
+            Some(MinFilter::NearestMipmapLinear) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear, true),
+            Some(MinFilter::LinearMipmapLinear) | None => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, true),
+        };
+        let all_linear = [mag_filter, min_filter, mipmap_filter]
+            .iter()
+            .all(|f| *f == wgpu::FilterMode::Linear);
+
+        wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: address_mode(sampler.wrap_s()),
+            address_mode_v: address_mode(sampler.wrap_t()),
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            lod_max_clamp: if use_mips { 32.0 } else { 0.0 },
+            anisotropy_clamp: if all_linear { 8 } else { 1 },
+            ..Default::default()
+        }
     }
 }
 
@@ -61,11 +311,14 @@ pub struct Vertex {
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
     pub color: [f32; 4],
+    /// xyz is the tangent direction, w the bitangent sign.
+    pub tangent: [f32; 4],
 }
 
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 4] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 3 => Float32x4];
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 3 => Float32x4, 4 => Float32x4
+    ];
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -94,285 +347,1099 @@ impl InstanceRaw {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fully opaque or fully transparent depending on `alpha_cutoff`, used for
+    /// foliage and hair cards.
+    Mask,
+    Blend,
+}
+
+impl From<gltf::material::AlphaMode> for AlphaMode {
+    fn from(mode: gltf::material::AlphaMode) -> Self {
+        match mode {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
+/// Scalar parameters of a glTF metallic-roughness material.
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialParams {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            emissive_factor: [0.0, 0.0, 0.0],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color_factor: [f32; 4],
+    emissive_factor: [f32; 3],
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    alpha_mode: u32,
+    _padding: [u32; 3],
+}
+
+impl From<&MaterialParams> for MaterialUniform {
+    fn from(params: &MaterialParams) -> Self {
+        Self {
+            base_color_factor: params.base_color_factor,
+            emissive_factor: params.emissive_factor,
+            metallic_factor: params.metallic_factor,
+            roughness_factor: params.roughness_factor,
+            normal_scale: params.normal_scale,
+            occlusion_strength: params.occlusion_strength,
+            alpha_cutoff: params.alpha_cutoff,
+            alpha_mode: match params.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask => 1,
+                AlphaMode::Blend => 2,
+            },
+            _padding: [0; 3],
+        }
+    }
+}
+
+pub struct MaterialTexture {
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+/// One texture per PBR slot, in bind group order.
+pub struct MaterialTextures {
+    pub base_color: MaterialTexture,
+    pub metallic_roughness: MaterialTexture,
+    pub normal: MaterialTexture,
+    pub occlusion: MaterialTexture,
+    pub emissive: MaterialTexture,
+}
+
+/// Neutral textures for material slots an asset leaves empty: white for
+/// colour, metallic-roughness, occlusion and emissive, and a flat normal.
+pub struct DefaultTextures {
+    white: wgpu::TextureView,
+    flat_normal: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+}
+
+impl DefaultTextures {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let white = texture::solid_color(
+            device,
+            queue,
+            [255, 255, 255, 255],
+            wgpu::TextureFormat::Rgba8Unorm,
+            "default_white_texture",
+        );
+        let flat_normal = texture::solid_color(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            wgpu::TextureFormat::Rgba8Unorm,
+            "default_normal_texture",
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("default_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self { white, flat_normal, sampler }
+    }
+
+    pub fn white(&self) -> MaterialTexture {
+        MaterialTexture { view: self.white.clone(), sampler: self.sampler.clone() }
+    }
+
+    pub fn flat_normal(&self) -> MaterialTexture {
+        MaterialTexture { view: self.flat_normal.clone(), sampler: self.sampler.clone() }
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture_view: wgpu::TextureView,
-    pub diffuse_sampler: wgpu::Sampler,
+    pub params: MaterialParams,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    /// Layout of group 1 in `shader.wgsl`: the material uniform followed by a
+    /// texture and sampler pair for each PBR slot.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                sampler_entry(2),
+                texture_entry(3),
+                sampler_entry(4),
+                texture_entry(5),
+                sampler_entry(6),
+                texture_entry(7),
+                sampler_entry(8),
+                texture_entry(9),
+                sampler_entry(10),
+            ],
+            label: Some("material_bind_group_layout"),
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        params: MaterialParams,
+        textures: &MaterialTextures,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{}_material_buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&params)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let slots = [
+            &textures.base_color,
+            &textures.metallic_roughness,
+            &textures.normal,
+            &textures.occlusion,
+            &textures.emissive,
+        ];
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (i, slot) in slots.iter().enumerate() {
+            let binding = 1 + 2 * i as u32;
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&slot.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&slot.sampler),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(&format!("{}_material_bind_group", name)),
+        });
+
+        Self { name: name.to_string(), params, bind_group }
+    }
+}
+
 pub struct Mesh {
-    pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
     pub material_index: usize,
+    /// Bounds of the vertices in model space.
+    pub bounds: Aabb,
+    /// Detail level this mesh belongs to, 0 being the most detailed.
+    pub lod: usize,
+}
+
+/// Which materials a draw call covers. Opaque and masked meshes go first with
+/// depth writes, blended meshes afterwards on top of them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlphaPass {
+    Opaque,
+    Blend,
+}
+
+impl AlphaPass {
+    pub fn includes(self, alpha_mode: AlphaMode) -> bool {
+        match self {
+            AlphaPass::Opaque => alpha_mode != AlphaMode::Blend,
+            AlphaPass::Blend => alpha_mode == AlphaMode::Blend,
+        }
+    }
 }
 
 pub trait Drawable<'a> {
-    fn draw_model(&mut self, model: &'a Model, instance_buffer: &'a wgpu::Buffer, instances: u32);
+    /// Draws the meshes of `model` listed in `meshes`, typically the ones that
+    /// survived culling, once for each instance in `instances`. The instance
+    /// buffer must already be bound at slot 1.
+    fn draw_model(
+        &mut self,
+        model: &'a Model,
+        meshes: &[usize],
+        instances: Range<u32>,
+        assets: &'a AssetManager,
+        pipelines: &'a MaterialPipelines,
+        pass: AlphaPass,
+    );
 }
 
-impl<'a, 'b> Drawable<'a> for wgpu::RenderPass<'b> where 'a: 'b {
-    fn draw_model(&mut self, model: &'a Model, instance_buffer: &'a wgpu::Buffer, instances: u32) {
-        self.set_vertex_buffer(1, instance_buffer.slice(..));
-        for mesh in &model.meshes {
-            let material = &model.materials[mesh.material_index];
+impl<'a> Drawable<'a> for wgpu::RenderPass<'_> {
+    fn draw_model(
+        &mut self,
+        model: &'a Model,
+        meshes: &[usize],
+        instances: Range<u32>,
+        assets: &'a AssetManager,
+        pipelines: &'a MaterialPipelines,
+        pass: AlphaPass,
+    ) {
+        for mesh in meshes.iter().filter_map(|&i| model.meshes.get(i)) {
+            let Some(material) = model
+                .materials
+                .get(mesh.material_index)
+                .and_then(|&handle| assets.material(handle))
+            else {
+                continue;
+            };
+            if !pass.includes(material.params.alpha_mode) {
+                continue;
+            }
+            self.set_pipeline(pipelines.select(&material.params));
             self.set_bind_group(1, &material.bind_group, &[]);
             self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
             self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            self.draw_indexed(0..mesh.num_indices, 0, 0..instances);
+            self.draw_indexed(0..mesh.num_indices, 0, instances.clone());
         }
     }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    pub materials: Vec<Handle<Material>>,
+    /// Union of the mesh bounds, in model space.
+    pub bounds: Aabb,
+    /// Number of detail levels the meshes are split into.
+    pub lod_count: usize,
 }
 
 impl Model {
-    pub fn from_heightmap(device: &wgpu::Device, queue: &wgpu::Queue, world: &World, material_bind_group_layout: &wgpu::BindGroupLayout) -> Result<Self> {
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-        let mut current_index: u16 = 0;
-
-        for z in 0..(WORLD_SIZE - 1) {
-            for x in 0..(WORLD_SIZE - 1) {
-                let y_tl = world.heightmap[x][z];
-                let y_tr = world.heightmap[x + 1][z];
-                let y_bl = world.heightmap[x][z + 1];
-                let y_br = world.heightmap[x + 1][z + 1];
-
-                let v_color = [1.0, 1.0, 1.0, 1.0];
-
-                let top_left  = Vertex { position: [x as f32, y_tl, z as f32], tex_coords: [0.0, 0.0], normal: [0.0, 1.0, 0.0], color: v_color };
-                let top_right = Vertex { position: [(x + 1) as f32, y_tr, z as f32], tex_coords: [1.0, 0.0], normal: [0.0, 1.0, 0.0], color: v_color };
-                let bottom_left = Vertex { position: [x as f32, y_bl, (z + 1) as f32], tex_coords: [0.0, 1.0], normal: [0.0, 1.0, 0.0], color: v_color };
-                let bottom_right= Vertex { position: [(x + 1) as f32, y_br, (z + 1) as f32], tex_coords: [1.0, 1.0], normal: [0.0, 1.0, 0.0], color: v_color };
-
-                vertices.extend_from_slice(&[top_left, top_right, bottom_left, bottom_right]);
-
-                indices.push(current_index);
-                indices.push(current_index + 2);
-                indices.push(current_index + 1);
-                indices.push(current_index + 1);
-                indices.push(current_index + 2);
-                indices.push(current_index + 3);
-                current_index += 4;
+    pub fn new(meshes: Vec<Mesh>, materials: Vec<Handle<Material>>) -> Self {
+        let bounds = meshes.iter().fold(Aabb::EMPTY, |bounds, mesh| bounds.union(mesh.bounds));
+        let lod_count = meshes.iter().map(|mesh| mesh.lod + 1).max().unwrap_or(1);
+        Self { meshes, materials, bounds, lod_count }
+    }
+
+    /// A grey box shown while the real model loads in the background.
+    pub fn placeholder(assets: &mut AssetManager) -> Self {
+        let material = match assets.acquire_material("placeholder") {
+            Some(handle) => handle,
+            None => {
+                let params = MaterialParams {
+                    base_color_factor: [0.5, 0.5, 0.5, 1.0],
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                };
+                assets.add_material("placeholder", "placeholder", params, MaterialSlots::default())
             }
-        }
+        };
+        let mesh = MeshData::placeholder_box(0.4, 1.8).upload(assets.device());
+        Self::new(vec![mesh], vec![material])
+    }
+}
+
+/// Mesh geometry on the CPU, built on a loader thread and uploaded later.
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub material_index: usize,
+    pub lod: usize,
+}
 
+impl MeshData {
+    pub fn upload(&self, device: &wgpu::Device) -> Mesh {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Landscape Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
+            label: Some(&format!("{} Vertex Buffer", self.name)),
+            contents: bytemuck::cast_slice(&self.vertices),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Landscape Index Buffer"),
-            contents: bytemuck::cast_slice(&indices),
+            label: Some(&format!("{} Index Buffer", self.name)),
+            contents: bytemuck::cast_slice(&self.indices),
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let landscape_mesh = Mesh {
-            name: "landscape".to_string(),
+        Mesh {
             vertex_buffer,
             index_buffer,
-            num_indices: indices.len() as u32,
-            material_index: 0,
-        };
+            num_indices: self.indices.len() as u32,
+            material_index: self.material_index,
+            bounds: Aabb::from_points(self.vertices.iter().map(|v| glam::Vec3::from(v.position))),
+            lod: self.lod,
+        }
+    }
 
-        let diffuse_bytes = include_bytes!("../res/stone.png");
-        let (_texture, view, sampler) = texture::from_bytes(device, queue, diffuse_bytes, "stone.png")?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: material_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
-                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&sampler) },
-            ],
-            label: Some("landscape_material_bind_group"),
-        });
+    /// An axis-aligned box standing on the origin, drawn in place of models that
+    /// are still loading and used to build simple ones in code.
+    pub fn placeholder_box(half_width: f32, height: f32) -> Self {
+        let faces: [([f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0]),
+        ];
+        let center = glam::Vec3::new(0.0, height * 0.5, 0.0);
+        let extent = glam::Vec3::new(half_width, height * 0.5, half_width);
 
-        let landscape_material = Material {
-            name: "stone".to_string(),
-            diffuse_texture_view: view,
-            diffuse_sampler: sampler,
-            bind_group,
-        };
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (normal, tangent) in faces {
+            let n = glam::Vec3::from(normal);
+            let t = glam::Vec3::from(tangent);
+            let b = n.cross(t);
+            let base = vertices.len() as u32;
+            for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let offset = n + t * (u * 2.0 - 1.0) + b * (v * 2.0 - 1.0);
+                vertices.push(Vertex {
+                    position: (center + offset * extent).to_array(),
+                    tex_coords: [u, 1.0 - v],
+                    normal,
+                    color: [1.0, 1.0, 1.0, 1.0],
+                    tangent: t.extend(1.0).to_array(),
+                });
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
 
-        Ok(Self {
-            meshes: vec![landscape_mesh],
-            materials: vec![landscape_material],
-        })
+        Self {
+            name: "placeholder".to_string(),
+            vertices,
+            indices,
+            material_index: 0,
+            lod: 0,
+        }
     }
 }
 
-pub fn load_gltf<P: AsRef<Path>>(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    path: P,
-) -> Result<Model> {
-    let (doc, buffers, images) = gltf::import(path.as_ref())?;
-
-    let texture_bind_group_layout =
-        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            label: Some("texture_bind_group_layout"),
-        });
+/// A decoded texture and the sampler the material asks for.
+pub struct TextureData {
+    pub key: String,
+    pub image: texture::ImageData,
+    pub sampler: wgpu::SamplerDescriptor<'static>,
+}
 
-    let white_pixel = [255, 255, 255, 255];
-    let fallback_texture = device.create_texture_with_data(
-        queue,
-        &wgpu::TextureDescriptor {
-            size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
-            mip_level_count: 1, sample_count: 1, dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING,
-            label: Some("fallback_white_texture"), view_formats: &[],
-        },
-        wgpu::util::TextureDataOrder::LayerMajor,
-        &white_pixel,
-    );
-    let fallback_view = fallback_texture.create_view(&wgpu::TextureViewDescriptor::default());
-    
-    let fallback_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Linear,
-        ..Default::default()
-    });
-
-    let mut materials = Vec::new();
-    for material in doc.materials() {
-        let pbr = material.pbr_metallic_roughness();
-
-        let (view, sampler) = if let Some(texture_info) = pbr.base_color_texture() {
-            let texture_source = &doc.textures().nth(texture_info.texture().index()).unwrap();
-            let image = &images[texture_source.source().index()];
-            let (_texture, view, sampler) = texture::from_bytes(device, queue, &image.pixels, "gltf_texture")?;
-            (view, sampler)
-        } else {
-            (fallback_view.clone(), fallback_sampler.clone())
-        };
+pub struct MaterialData {
+    pub key: String,
+    pub name: String,
+    pub params: MaterialParams,
+    pub textures: MaterialSlots<TextureData>,
+}
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("material_bind_group"),
-        });
+/// Everything in a glTF file that can be prepared without the GPU.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+}
 
-        materials.push(Material {
-            name: material.name().unwrap_or_default().to_string(),
-            diffuse_texture_view: view,
-            diffuse_sampler: sampler,
-            bind_group,
-        });
-    }
+/// Parses a glTF file and builds its meshes and images on the CPU. Safe to call
+/// from any thread. `features` decide which compressed formats KTX2 textures
+/// can keep.
+pub fn decode_gltf(path: &Path, features: wgpu::Features) -> Result<ModelData, GltfError> {
+    let import_error = |source| GltfError::Import { path: path.to_path_buf(), source };
+    let gltf::Gltf { document: doc, blob } = gltf::Gltf::open(path).map_err(import_error)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+    let buffers = gltf::import_buffers(&doc, Some(base), blob).map_err(import_error)?;
+    let images = doc
+        .images()
+        .map(|image| read_gltf_image(&image, base, &buffers))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(import_error)?;
+    decode_document(path, &doc, &buffers, &images, features)
+}
 
-    if materials.is_empty() {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&fallback_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&fallback_sampler),
-                },
-            ],
-            label: Some("fallback_material_bind_group"),
-        });
-        materials.push(Material {
-            name: "fallback_material".to_string(),
-            diffuse_texture_view: fallback_view,
-            diffuse_sampler: fallback_sampler,
-            bind_group,
-        });
-    }
+fn decode_document(
+    path: &Path,
+    doc: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    images: &[GltfImage],
+    features: wgpu::Features,
+) -> Result<ModelData, GltfError> {
+    // Primitives without a material use the glTF default one, kept after the
+    // document's own.
+    let default_material = doc.materials().len();
 
     let mut meshes = Vec::new();
     for scene in doc.scenes() {
         for node in scene.nodes() {
             if let Some(mesh) = node.mesh() {
+                let lod = node.name().and_then(lod_from_name).or_else(|| mesh.name().and_then(lod_from_name));
                 for primitive in mesh.primitives() {
-                    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                    let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
-                    let normals: Vec<[f32; 3]> = reader.read_normals().unwrap().collect();
-                    
-                    let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
-                        Some(coords) => coords.into_f32().collect(),
-                        None => vec![[0.0, 0.0]; positions.len()],
-                    };
-
-                    let colors: Vec<[f32; 4]> = match reader.read_colors(0) {
-                        Some(colors) => colors.into_rgba_f32().collect(),
-                        None => vec![[1.0, 1.0, 1.0, 1.0]; positions.len()],
-                    };
-
-                    let vertices: Vec<Vertex> = positions.iter().zip(normals.iter()).zip(tex_coords.iter()).zip(colors.iter())
-                        .map(|(((pos, norm), tc), col)| Vertex {
-                            position: *pos,
-                            tex_coords: *tc,
-                            normal: *norm,
-                            color: *col,
-                        })
-                        .collect();
-
-                    let vertex_buffer =
-                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some("GLTF Vertex Buffer"),
-                            contents: bytemuck::cast_slice(&vertices),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
-
-                    let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
-                    let index_buffer =
-                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some("GLTF Index Buffer"),
-                            contents: bytemuck::cast_slice(&indices),
-                            usage: wgpu::BufferUsages::INDEX,
-                        });
-
-                    meshes.push(Mesh {
+                    let (vertices, indices) = read_primitive(&primitive, buffers)
+                        .map_err(|kind| GltfError::Primitive {
+                            path: path.to_path_buf(),
+                            mesh: mesh_label(&mesh),
+                            primitive: primitive.index(),
+                            kind,
+                        })?;
+
+                    meshes.push(MeshData {
                         name: mesh.name().unwrap_or_default().to_string(),
-                        vertex_buffer,
-                        index_buffer,
-                        num_indices: indices.len() as u32,
-                        material_index: primitive.material().index().unwrap_or(0),
+                        vertices,
+                        indices,
+                        material_index: primitive.material().index().unwrap_or(default_material),
+                        lod: lod.unwrap_or(0),
                     });
                 }
             }
         }
     }
 
-    Ok(Model { meshes, materials })
-}
\ No newline at end of file
+    let mut materials = doc
+        .materials()
+        .map(|material| decode_gltf_material(path, &material, images, features))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if meshes.iter().any(|mesh| mesh.material_index == default_material) {
+        materials.push(MaterialData {
+            key: format!("{}#default_material", path.display()),
+            name: "fallback_material".to_string(),
+            params: MaterialParams::default(),
+            textures: MaterialSlots::default(),
+        });
+    }
+
+    Ok(ModelData { meshes, materials })
+}
+
+/// Detail level from a `_LOD<n>` name suffix, the convention exporters use
+/// for alternative versions of a mesh.
+fn lod_from_name(name: &str) -> Option<usize> {
+    let (_, level) = name.rsplit_once("_LOD").or_else(|| name.rsplit_once("_lod"))?;
+    level.parse().ok()
+}
+
+fn decode_gltf_material(
+    path: &Path,
+    material: &gltf::Material,
+    images: &[GltfImage],
+    features: wgpu::Features,
+) -> Result<MaterialData, GltfError> {
+    let decode = |slot: &'static str, texture: Option<gltf::Texture>, srgb: bool| {
+        texture
+            .map(|texture| decode_gltf_texture(path, slot, &texture, images, srgb, features))
+            .transpose()
+            .map_err(|kind| GltfError::Material {
+                path: path.to_path_buf(),
+                material: material_label(material),
+                kind,
+            })
+    };
+
+    let pbr = material.pbr_metallic_roughness();
+    let textures = MaterialSlots {
+        base_color: decode("base color", pbr.base_color_texture().map(|info| info.texture()), true)?,
+        metallic_roughness: decode(
+            "metallic-roughness",
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+            false,
+        )?,
+        normal: decode("normal", material.normal_texture().map(|normal| normal.texture()), false)?,
+        occlusion: decode("occlusion", material.occlusion_texture().map(|occlusion| occlusion.texture()), false)?,
+        emissive: decode("emissive", material.emissive_texture().map(|info| info.texture()), true)?,
+    };
+
+    let params = MaterialParams {
+        base_color_factor: pbr.base_color_factor(),
+        emissive_factor: material.emissive_factor(),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        normal_scale: material.normal_texture().map_or(1.0, |n| n.scale()),
+        occlusion_strength: material.occlusion_texture().map_or(1.0, |o| o.strength()),
+        alpha_mode: material.alpha_mode().into(),
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+    };
+
+    Ok(MaterialData {
+        key: format!("{}#material{}", path.display(), material.index().unwrap_or(usize::MAX)),
+        name: material.name().unwrap_or_default().to_string(),
+        params,
+        textures,
+    })
+}
+
+/// An image referenced by a glTF file. KTX2 images are kept encoded, as the
+/// `gltf` crate only decodes PNG and JPEG.
+enum GltfImage {
+    Decoded(gltf::image::Data),
+    Ktx2(Vec<u8>),
+}
+
+fn read_gltf_image(
+    image: &gltf::Image,
+    base: &Path,
+    buffers: &[gltf::buffer::Data],
+) -> Result<GltfImage, gltf::Error> {
+    use gltf::image::Source;
+
+    match image.source() {
+        Source::View { view, mime_type: "image/ktx2" } => {
+            let buffer = &buffers[view.buffer().index()];
+            Ok(GltfImage::Ktx2(buffer[view.offset()..view.offset() + view.length()].to_vec()))
+        }
+        Source::Uri { uri, mime_type } if mime_type == Some("image/ktx2") || uri.ends_with(".ktx2") => {
+            std::fs::read(base.join(uri)).map(GltfImage::Ktx2).map_err(gltf::Error::Io)
+        }
+        source => gltf::image::Data::from_source(source, Some(base), buffers).map(GltfImage::Decoded),
+    }
+}
+
+/// Image index of a texture's `KHR_texture_basisu` source, the KTX2 image
+/// preferred over the texture's plain source when both are given.
+fn ktx2_source(texture: &gltf::Texture) -> Option<usize> {
+    let source = texture.extension_value("KHR_texture_basisu")?.get("source")?.as_u64()?;
+    Some(source as usize)
+}
+
+/// `slot` names the material slot the texture is used by, for errors.
+fn decode_gltf_texture(
+    path: &Path,
+    slot: &'static str,
+    texture: &gltf::Texture,
+    images: &[GltfImage],
+    srgb: bool,
+    features: wgpu::Features,
+) -> Result<TextureData, MaterialErrorKind> {
+    let decode = |image_index: usize| {
+        let image = match images.get(image_index) {
+            Some(GltfImage::Decoded(image)) => texture::ImageData::from_gltf(image, srgb),
+            // KTX2 images carry their own colour space in their format.
+            Some(GltfImage::Ktx2(bytes)) => crate::ktx::decode(bytes, features),
+            None => return Err(MaterialErrorKind::MissingImage { slot, image: image_index }),
+        };
+        Ok((image_index, image))
+    };
+
+    let fallback = texture.source().map(|image| image.index());
+    let (image_index, image) = match ktx2_source(texture) {
+        Some(index) => match (decode(index)?, fallback) {
+            ((_, Err(e)), Some(fallback)) => {
+                log::warn!(
+                    "{}: {} texture's KTX2 image {} failed to load, using image {}: {:#}",
+                    path.display(),
+                    slot,
+                    index,
+                    fallback,
+                    e
+                );
+                decode(fallback)?
+            }
+            (decoded, _) => decoded,
+        },
+        None => decode(fallback.ok_or(MaterialErrorKind::NoImage { slot })?)?,
+    };
+    // The same image may be sampled as colour by one slot and as data by
+    // another, which needs two differently formatted textures.
+    let color_space = if srgb { "srgb" } else { "linear" };
+    Ok(TextureData {
+        key: format!("{}#image{}_{}", path.display(), image_index, color_space),
+        image: image.map_err(MaterialErrorKind::Texture)?,
+        sampler: texture::sampler_from_gltf(&texture.sampler()),
+    })
+}
+
+/// Creates the GPU buffers, textures and materials for decoded model data.
+/// Must run on the thread that owns the device.
+pub fn upload_model(assets: &mut AssetManager, data: ModelData) -> Model {
+    let meshes = data.meshes.iter().map(|mesh| mesh.upload(assets.device())).collect();
+
+    let materials = data
+        .materials
+        .into_iter()
+        .map(|material| {
+            if let Some(handle) = assets.acquire_material(&material.key) {
+                return handle;
+            }
+            let slots = material.textures.map(|texture| TextureSlot {
+                texture: assets.add_texture(&texture.key, &texture.image),
+                sampler: Some(assets.device().create_sampler(&texture.sampler)),
+            });
+            assets.add_material(&material.key, &material.name, material.params, slots)
+        })
+        .collect();
+
+    Model::new(meshes, materials)
+}
+
+/// Error returned by [`decode_gltf`], carrying the file and the part of the
+/// document that could not be loaded.
+#[derive(Debug)]
+pub enum GltfError {
+    Import {
+        path: PathBuf,
+        source: gltf::Error,
+    },
+    Material {
+        path: PathBuf,
+        material: String,
+        kind: MaterialErrorKind,
+    },
+    Primitive {
+        path: PathBuf,
+        mesh: String,
+        primitive: usize,
+        kind: PrimitiveErrorKind,
+    },
+}
+
+#[derive(Debug)]
+pub enum MaterialErrorKind {
+    MissingImage { slot: &'static str, image: usize },
+    NoImage { slot: &'static str },
+    Texture(anyhow::Error),
+}
+
+#[derive(Debug)]
+pub enum PrimitiveErrorKind {
+    MissingPositions,
+    UnsupportedMode(gltf::mesh::Mode),
+    IncompleteTriangles(usize),
+    IndexOutOfRange { index: u32, vertex_count: usize },
+    AttributeCount { attribute: &'static str, count: usize, vertex_count: usize },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Import { path, source } => {
+                write!(f, "failed to import {}: {}", path.display(), source)
+            }
+            GltfError::Material { path, material, kind } => {
+                write!(f, "{}: material {}: {}", path.display(), material, kind)
+            }
+            GltfError::Primitive { path, mesh, primitive, kind } => write!(
+                f,
+                "{}: mesh {}, primitive {}: {}",
+                path.display(),
+                mesh,
+                primitive,
+                kind
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MaterialErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaterialErrorKind::MissingImage { slot, image } => {
+                write!(f, "{} texture references missing image {}", slot, image)
+            }
+            MaterialErrorKind::NoImage { slot } => write!(f, "{} texture has no image", slot),
+            MaterialErrorKind::Texture(e) => write!(f, "failed to create texture: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for PrimitiveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimitiveErrorKind::MissingPositions => write!(f, "no POSITION attribute"),
+            PrimitiveErrorKind::UnsupportedMode(mode) => {
+                write!(f, "unsupported primitive mode {:?}, only triangles can be drawn", mode)
+            }
+            PrimitiveErrorKind::IncompleteTriangles(count) => {
+                write!(f, "{} indices do not form whole triangles", count)
+            }
+            PrimitiveErrorKind::IndexOutOfRange { index, vertex_count } => write!(
+                f,
+                "index {} is out of range for {} vertices",
+                index, vertex_count
+            ),
+            PrimitiveErrorKind::AttributeCount { attribute, count, vertex_count } => write!(
+                f,
+                "{} has {} values for {} vertices",
+                attribute, count, vertex_count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Import { source, .. } => Some(source),
+            GltfError::Material { kind: MaterialErrorKind::Texture(e), .. } => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+fn mesh_label(mesh: &gltf::Mesh) -> String {
+    match mesh.name() {
+        Some(name) => format!("'{}' (#{})", name, mesh.index()),
+        None => format!("#{}", mesh.index()),
+    }
+}
+
+fn material_label(material: &gltf::Material) -> String {
+    match (material.name(), material.index()) {
+        (Some(name), Some(index)) => format!("'{}' (#{})", name, index),
+        (None, Some(index)) => format!("#{}", index),
+        (Some(name), None) => format!("'{}'", name),
+        (None, None) => "default".to_string(),
+    }
+}
+
+/// Reads one primitive into a triangle list. Missing indices are treated as a
+/// non-indexed draw, strips and fans are unrolled, and missing normals and
+/// tangents are generated from the triangle faces.
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<(Vec<Vertex>, Vec<u32>), PrimitiveErrorKind> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or(PrimitiveErrorKind::MissingPositions)?
+        .collect();
+    let vertex_count = positions.len();
+
+    let raw_indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_count as u32).collect(),
+    };
+    if let Some(&index) = raw_indices.iter().find(|&&i| i as usize >= vertex_count) {
+        return Err(PrimitiveErrorKind::IndexOutOfRange { index, vertex_count });
+    }
+    let indices = triangulate(primitive.mode(), &raw_indices)?;
+
+    let normals: Vec<[f32; 3]> = match reader.read_normals() {
+        Some(normals) => check_count("NORMAL", normals.collect(), vertex_count)?,
+        None => generate_normals(&positions, &indices),
+    };
+
+    let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+        Some(coords) => check_count("TEXCOORD_0", coords.into_f32().collect(), vertex_count)?,
+        None => vec![[0.0, 0.0]; vertex_count],
+    };
+
+    let colors: Vec<[f32; 4]> = match reader.read_colors(0) {
+        Some(colors) => check_count("COLOR_0", colors.into_rgba_f32().collect(), vertex_count)?,
+        None => vec![[1.0, 1.0, 1.0, 1.0]; vertex_count],
+    };
+
+    let tangents: Vec<[f32; 4]> = match reader.read_tangents() {
+        Some(tangents) => check_count("TANGENT", tangents.collect(), vertex_count)?,
+        None => generate_tangents(&positions, &normals, &tex_coords, &indices),
+    };
+
+    let vertices = positions
+        .iter()
+        .zip(normals.iter())
+        .zip(tex_coords.iter())
+        .zip(colors.iter())
+        .zip(tangents.iter())
+        .map(|((((pos, norm), tc), col), tan)| Vertex {
+            position: *pos,
+            tex_coords: *tc,
+            normal: *norm,
+            color: *col,
+            tangent: *tan,
+        })
+        .collect();
+
+    Ok((vertices, indices))
+}
+
+/// Every attribute must have one value per POSITION, or zipping them into
+/// vertices would silently drop the tail.
+fn check_count<T>(
+    attribute: &'static str,
+    values: Vec<T>,
+    vertex_count: usize,
+) -> Result<Vec<T>, PrimitiveErrorKind> {
+    if values.len() != vertex_count {
+        return Err(PrimitiveErrorKind::AttributeCount { attribute, count: values.len(), vertex_count });
+    }
+    Ok(values)
+}
+
+fn triangulate(mode: gltf::mesh::Mode, indices: &[u32]) -> Result<Vec<u32>, PrimitiveErrorKind> {
+    use gltf::mesh::Mode;
+
+    match mode {
+        Mode::Triangles => {
+            if !indices.len().is_multiple_of(3) {
+                return Err(PrimitiveErrorKind::IncompleteTriangles(indices.len()));
+            }
+            Ok(indices.to_vec())
+        }
+        Mode::TriangleStrip => Ok(indices
+            .windows(3)
+            .enumerate()
+            .flat_map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] })
+            .collect()),
+        Mode::TriangleFan => Ok(indices
+            .windows(2)
+            .skip(1)
+            .flat_map(|w| [indices[0], w[0], w[1]])
+            .collect()),
+        other => Err(PrimitiveErrorKind::UnsupportedMode(other)),
+    }
+}
+
+/// Smooth normals from area-weighted face normals shared through the index buffer.
+fn generate_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![glam::Vec3::ZERO; positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| glam::Vec3::from(positions[i as usize]));
+        let face_normal = (b - a).cross(c - a);
+        for &i in tri {
+            normals[i as usize] += face_normal;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| n.try_normalize().unwrap_or(glam::Vec3::Y).to_array())
+        .collect()
+}
+
+/// Per-vertex tangents from triangle UV gradients, Gram-Schmidt orthogonalised
+/// against the normal. The w component holds the bitangent handedness.
+fn generate_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    indices: &[u32],
+) -> Vec<[f32; 4]> {
+    use glam::{Vec2, Vec3};
+
+    let mut tangents = vec![Vec3::ZERO; positions.len()];
+    let mut bitangents = vec![Vec3::ZERO; positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| i as usize);
+        let edge1 = Vec3::from(positions[b]) - Vec3::from(positions[a]);
+        let edge2 = Vec3::from(positions[c]) - Vec3::from(positions[a]);
+        let duv1 = Vec2::from(tex_coords[b]) - Vec2::from(tex_coords[a]);
+        let duv2 = Vec2::from(tex_coords[c]) - Vec2::from(tex_coords[a]);
+
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) * r;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(bitangents.iter()))
+        .map(|(n, (t, b))| {
+            let n = Vec3::from(*n);
+            let t = (*t - n * n.dot(*t))
+                .try_normalize()
+                .unwrap_or_else(|| n.any_orthonormal_vector());
+            let w = if n.cross(t).dot(*b) < 0.0 { -1.0 } else { 1.0 };
+            t.extend(w).to_array()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::mesh::Mode;
+
+    #[test]
+    fn fan_quad_becomes_two_triangles() {
+        let triangles = triangulate(Mode::TriangleFan, &[0, 1, 2, 3]).unwrap();
+        assert_eq!(triangles, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn strip_quad_keeps_winding() {
+        let triangles = triangulate(Mode::TriangleStrip, &[0, 1, 2, 3]).unwrap();
+        assert_eq!(triangles, vec![0, 1, 2, 2, 1, 3]);
+    }
+
+    #[test]
+    fn triangle_lists_must_be_whole() {
+        assert_eq!(triangulate(Mode::Triangles, &[0, 1, 2]).unwrap(), vec![0, 1, 2]);
+        assert!(matches!(
+            triangulate(Mode::Triangles, &[0, 1, 2, 3]),
+            Err(PrimitiveErrorKind::IncompleteTriangles(4))
+        ));
+    }
+
+    #[test]
+    fn non_triangle_modes_are_rejected() {
+        assert!(matches!(
+            triangulate(Mode::Lines, &[0, 1]),
+            Err(PrimitiveErrorKind::UnsupportedMode(Mode::Lines))
+        ));
+    }
+
+    #[test]
+    fn short_attributes_are_rejected() {
+        // Three positions but only two normals, all zero.
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 60, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA" }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 24 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0, 0, 0], "max": [0, 0, 0] },
+                { "bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC3" }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "NORMAL": 1 } }] }]
+        }"#;
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&document, Some(Path::new(".")), blob).unwrap();
+        let primitive = document.meshes().next().unwrap().primitives().next().unwrap();
+
+        let error = read_primitive(&primitive, &buffers).unwrap_err();
+        assert!(matches!(
+            error,
+            PrimitiveErrorKind::AttributeCount { attribute: "NORMAL", count: 2, vertex_count: 3 }
+        ));
+    }
+
+    #[test]
+    fn primitives_without_a_material_get_the_default_one() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 36, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA" }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                            "min": [0, 0, 0], "max": [0, 0, 0] }],
+            "materials": [{ "name": "glass", "alphaMode": "BLEND" }],
+            "meshes": [{ "primitives": [
+                { "attributes": { "POSITION": 0 }, "material": 0 },
+                { "attributes": { "POSITION": 0 } }
+            ] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let path = Path::new("mixed.gltf");
+        let buffers = gltf::import_buffers(&document, Some(Path::new(".")), blob).unwrap();
+
+        let data = decode_document(path, &document, &buffers, &[], wgpu::Features::empty()).unwrap();
+        assert_eq!(data.meshes.iter().map(|mesh| mesh.material_index).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(data.materials.len(), 2);
+        assert_eq!(data.materials[0].params.alpha_mode, AlphaMode::Blend);
+        assert_eq!(data.materials[1].params.alpha_mode, AlphaMode::Opaque);
+    }
+
+    #[test]
+    fn missing_image_names_its_slot() {
+        let kind = MaterialErrorKind::MissingImage { slot: "normal", image: 3 };
+        assert_eq!(kind.to_string(), "normal texture references missing image 3");
+    }
+
+    #[test]
+    fn flat_triangle_faces_up() {
+        let positions = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]];
+        assert_eq!(generate_normals(&positions, &[0, 1, 2]), vec![[0.0, 1.0, 0.0]; 3]);
+    }
+
+    #[test]
+    fn shared_vertices_average_their_faces() {
+        // Two faces folded along the z axis, one facing +x and one facing up.
+        let positions = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]];
+        let normals = generate_normals(&positions, &[0, 1, 2, 0, 1, 3]);
+        let diagonal = glam::Vec3::new(1.0, 1.0, 0.0).normalize().to_array();
+        assert_eq!(normals[2], [0.0, 1.0, 0.0]);
+        assert_eq!(normals[3], [1.0, 0.0, 0.0]);
+        for shared in [normals[0], normals[1]] {
+            assert!(glam::Vec3::from(shared).abs_diff_eq(glam::Vec3::from(diagonal), 1e-6));
+        }
+    }
+
+    #[test]
+    fn unused_and_degenerate_vertices_default_up() {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [5.0, 5.0, 5.0]];
+        assert_eq!(generate_normals(&positions, &[0, 1, 2]), vec![[0.0, 1.0, 0.0]; 4]);
+    }
+
+    #[test]
+    fn embedded_ktx2_images_are_kept_encoded() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_texture_basisu"],
+            "extensionsRequired": ["KHR_texture_basisu"],
+            "buffers": [{ "byteLength": 4, "uri": "data:application/octet-stream;base64,AQIDBA==" }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 1, "byteLength": 2 }],
+            "images": [{ "bufferView": 0, "mimeType": "image/ktx2" }],
+            "textures": [{ "extensions": { "KHR_texture_basisu": { "source": 0 } } }]
+        }"#;
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let base = Path::new(".");
+        let buffers = gltf::import_buffers(&document, Some(base), blob).unwrap();
+
+        let image = read_gltf_image(&document.images().next().unwrap(), base, &buffers).unwrap();
+        assert!(matches!(image, GltfImage::Ktx2(bytes) if bytes == [2, 3]));
+        let texture = document.textures().next().unwrap();
+        assert_eq!(ktx2_source(&texture), Some(0));
+        assert!(texture.source().is_none());
+    }
+}
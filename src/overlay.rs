@@ -0,0 +1,226 @@
+use crate::post_process::HDR_FORMAT;
+use crate::world::{self, World, WORLD_SIZE};
+use glam::{Vec2, Vec3};
+use std::time::Instant;
+
+/// Colour of the marker for walking somewhere.
+pub const WALK_MARKER_COLOR: [f32; 3] = [1.0, 0.9, 0.0];
+/// Colour of the marker for interacting with an entity.
+pub const INTERACT_MARKER_COLOR: [f32; 3] = [1.0, 0.05, 0.0];
+/// Seconds a click marker stays up, fading all the while.
+const MARKER_LIFETIME: f32 = 0.8;
+/// Width of a click marker in world units.
+const MARKER_SIZE: f32 = 0.6;
+/// Width of each arm of a marker's X, as a fraction of its size.
+const MARKER_THICKNESS: f32 = 0.18;
+/// Pieces each arm of a marker is split into to follow the ground.
+const MARKER_SEGMENTS: usize = 4;
+/// Width of grid lines and of tile outlines in world units.
+const GRID_LINE_WIDTH: f32 = 0.03;
+const OUTLINE_WIDTH: f32 = 0.07;
+/// Pulls shapes towards the camera by this many steps of depth precision,
+/// plus more on ground seen at a grazing angle, so they win against the
+/// terrain they lie on but not against anything in front of it.
+const DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState { constant: -300, slope_scale: -2.0, clamp: 0.0 };
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Flat shapes laid on the terrain, such as click markers and tile
+/// highlights, rebuilt every frame and drawn blended over the scene and its
+/// water. They are depth tested with a bias so they don't fight the ground
+/// they lie on, while terrain and models in front still hide them.
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    vertices: Vec<OverlayVertex>,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+}
+
+impl Overlay {
+    /// `sample_count` is that of the scene's depth buffer.
+    pub fn new(device: &wgpu::Device, camera_bind_group_layout: &wgpu::BindGroupLayout, sample_count: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("overlay.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                }],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            // Shapes are seen from below when the camera dips under a hill.
+            primitive: wgpu::PrimitiveState { cull_mode: None, ..Default::default() },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: DEPTH_BIAS,
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            vertices: Vec::new(),
+            vertex_buffer: Self::create_buffer(device, 0),
+            num_vertices: 0,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, vertices: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            size: (vertices.max(1) * std::mem::size_of::<OverlayVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Clears the shapes of the last frame.
+    pub fn begin(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// An X of `size` across, centred on `point` and lying on the terrain.
+    pub fn add_marker(&mut self, world: &World, point: Vec3, color: [f32; 4], size: f32) {
+        let half = size * 0.5;
+        let width = size * MARKER_THICKNESS * 0.5;
+        for direction in [Vec2::new(1.0, 1.0), Vec2::new(1.0, -1.0)] {
+            let along = direction.normalize();
+            let across = along.perp() * width;
+            let center = Vec2::new(point.x, point.z);
+            let at = |offset: Vec2| {
+                let p = center + offset;
+                Vec3::new(p.x, world.surface_height(p.x, p.y), p.y)
+            };
+            for segment in 0..MARKER_SEGMENTS {
+                let start = along * (half * (2.0 * segment as f32 / MARKER_SEGMENTS as f32 - 1.0));
+                let end = along * (half * (2.0 * (segment + 1) as f32 / MARKER_SEGMENTS as f32 - 1.0));
+                self.add_quad([at(start - across), at(start + across), at(end - across), at(end + across)], color);
+            }
+        }
+    }
+
+    /// Covers the tile with its lowest corner at `(x, z)`, split along the
+    /// same diagonal as the terrain so it lies flush on it.
+    pub fn add_tile(&mut self, world: &World, x: usize, z: usize, color: [f32; 4]) {
+        let corner = |dx: usize, dz: usize| Vec3::new((x + dx) as f32, world.heightmap[x + dx][z + dz], (z + dz) as f32);
+        self.add_quad([corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1)], color);
+    }
+
+    /// Outlines the tile with its lowest corner at `(x, z)`.
+    pub fn add_tile_outline(&mut self, world: &World, x: usize, z: usize, color: [f32; 4]) {
+        let corner = |dx: usize, dz: usize| Vec3::new((x + dx) as f32, world.heightmap[x + dx][z + dz], (z + dz) as f32);
+        let corners = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+        for i in 0..4 {
+            self.add_line(corners[i], corners[(i + 1) % 4], color, OUTLINE_WIDTH);
+        }
+    }
+
+    /// Lines along the tile edges within `radius` tiles of `center`, at the
+    /// heightmap's samples like the terrain mesh, so the grid follows the
+    /// ground.
+    pub fn add_grid(&mut self, world: &World, center: Vec3, radius: usize, color: [f32; 4]) {
+        let (center_x, center_z) = world::tile_at(center);
+        let x_range = center_x.saturating_sub(radius)..(center_x + radius + 1).min(WORLD_SIZE - 1);
+        let z_range = center_z.saturating_sub(radius)..(center_z + radius + 1).min(WORLD_SIZE - 1);
+        let point = |x: usize, z: usize| Vec3::new(x as f32, world.heightmap[x][z], z as f32);
+        for x in x_range.start..=x_range.end {
+            for z in z_range.clone() {
+                self.add_line(point(x, z), point(x, z + 1), color, GRID_LINE_WIDTH);
+            }
+        }
+        for z in z_range.start..=z_range.end {
+            for x in x_range.clone() {
+                self.add_line(point(x, z), point(x + 1, z), color, GRID_LINE_WIDTH);
+            }
+        }
+    }
+
+    /// A strip of `width` from `from` to `to`, widened across the ground.
+    fn add_line(&mut self, from: Vec3, to: Vec3, color: [f32; 4], width: f32) {
+        let across = (to - from).with_y(0.0).normalize_or_zero().cross(Vec3::Y) * (width * 0.5);
+        self.add_quad([from - across, from + across, to - across, to + across], color);
+    }
+
+    /// Two triangles sharing the diagonal from `corners[1]` to `corners[2]`.
+    fn add_quad(&mut self, corners: [Vec3; 4], color: [f32; 4]) {
+        let vertex = |i: usize| OverlayVertex { position: corners[i].to_array(), color };
+        self.vertices.extend([0, 2, 1, 1, 2, 3].map(vertex));
+    }
+
+    /// Sends this frame's shapes to the GPU, growing the buffer if needed.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let size = (self.vertices.len() * std::mem::size_of::<OverlayVertex>()) as u64;
+        if size > self.vertex_buffer.size() {
+            self.vertex_buffer = Self::create_buffer(device, self.vertices.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.num_vertices = self.vertices.len() as u32;
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        if self.num_vertices == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+/// Where the player last clicked, shown as an X that fades out.
+pub struct ClickMarker {
+    point: Vec3,
+    color: [f32; 3],
+    placed: Instant,
+}
+
+impl ClickMarker {
+    pub fn new(point: Vec3, color: [f32; 3]) -> Self {
+        Self { point, color, placed: Instant::now() }
+    }
+
+    /// Adds the marker to `overlay`, returning false once it has faded out.
+    pub fn draw(&self, overlay: &mut Overlay, world: &World) -> bool {
+        let age = self.placed.elapsed().as_secs_f32();
+        if age >= MARKER_LIFETIME {
+            return false;
+        }
+        let [r, g, b] = self.color;
+        overlay.add_marker(world, self.point, [r, g, b, 1.0 - age / MARKER_LIFETIME], MARKER_SIZE);
+        true
+    }
+}
@@ -1,5 +1,11 @@
+use crate::world;
 use glam::Vec3;
 
+/// Distance the player walks per second.
+pub const WALK_SPEED: f32 = 12.0;
+/// Spacing of the points sampled along a walk to find the tiles it crosses.
+const PATH_SAMPLE_SPACING: f32 = 0.1;
+
 pub struct Player {
     pub position: Vec3,
     pub target_position: Option<Vec3>, 
@@ -9,4 +15,21 @@ impl Player {
     pub fn new(position: Vec3) -> Self {
         Self { position, target_position: None }
     }
+
+    /// The tiles the player crosses on the straight walk to its target, in
+    /// order, ending with the target's.
+    pub fn planned_tiles(&self) -> Vec<(usize, usize)> {
+        let Some(target) = self.target_position else {
+            return Vec::new();
+        };
+        let samples = ((target - self.position).length() / PATH_SAMPLE_SPACING).ceil().max(1.0) as usize;
+        let mut tiles = Vec::new();
+        for i in 0..=samples {
+            let tile = world::tile_at(self.position.lerp(target, i as f32 / samples as f32));
+            if tiles.last() != Some(&tile) {
+                tiles.push(tile);
+            }
+        }
+        tiles
+    }
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+struct PostUniform {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    contrast: f32,
+    saturation: f32,
+    flags: u32,
+    tint: vec4<f32>,
+}
+
+const FLAG_TONE_MAPPING: u32 = 1u;
+const FLAG_BLOOM: u32 = 2u;
+const FLAG_COLOR_GRADING: u32 = 4u;
+
+const FXAA_EDGE_THRESHOLD: f32 = 0.125;
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+const FXAA_SPAN_MAX: f32 = 8.0;
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(2)
+var s_linear: sampler;
+@group(0) @binding(3)
+var<uniform> post: PostUniform;
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen, with texture coordinates 0..1 over the
+// visible part.
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_input(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_input, s_linear, uv, 0.0).rgb;
+}
+
+// Keeps the part of each pixel brighter than the bloom threshold.
+@fragment
+fn fs_bloom_bright(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = sample_input(in.uv) * post.exposure;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - post.bloom_threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// Nine-tap Gaussian, using bilinear filtering to read two texels per sample.
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let texel = direction / vec2<f32>(textureDimensions(t_input));
+    var color = sample_input(uv) * 0.2270270270;
+    color += (sample_input(uv + texel * 1.3846153846) + sample_input(uv - texel * 1.3846153846)) * 0.3162162162;
+    color += (sample_input(uv + texel * 3.2307692308) + sample_input(uv - texel * 3.2307692308)) * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_blur_x(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_y(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var color = sample_input(in.uv) * post.exposure;
+    let bloom = textureSampleLevel(t_bloom, s_linear, in.uv, 0.0).rgb;
+    if (post.flags & FLAG_BLOOM) != 0u {
+        color += bloom * post.bloom_intensity;
+    }
+
+    if (post.flags & FLAG_TONE_MAPPING) != 0u {
+        color = aces(color);
+    } else {
+        color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+
+    if (post.flags & FLAG_COLOR_GRADING) != 0u {
+        let luma = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+        color = mix(vec3<f32>(luma), color, post.saturation);
+        // Contrast pivots around middle grey.
+        color = (color - 0.18) * post.contrast + 0.18;
+        color = clamp(color * post.tint.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Perceptual brightness; the input is linear.
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+@fragment
+fn fs_fxaa(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let rgb_m = sample_input(in.uv);
+    let luma_m = luma(rgb_m);
+    let luma_nw = luma(sample_input(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_input(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_input(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_input(in.uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+    if luma_max - luma_min < max(FXAA_EDGE_THRESHOLD_MIN, luma_max * FXAA_EDGE_THRESHOLD) {
+        return vec4<f32>(rgb_m, 1.0);
+    }
+
+    // Blur along the edge, which runs perpendicular to the luma gradient.
+    var dir = vec2<f32>(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let rgb_a = 0.5 * (sample_input(in.uv + dir * (1.0 / 3.0 - 0.5)) + sample_input(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_input(in.uv - dir * 0.5) + sample_input(in.uv + dir * 0.5));
+    let luma_b = luma(rgb_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -0,0 +1,229 @@
+use crate::render_graph::RenderGraph;
+use crate::settings::GraphicsSettings;
+use wgpu::util::DeviceExt;
+
+/// Format the scene is rendered in before tone mapping.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Format of the tone-mapped image when FXAA runs after it.
+pub const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Attachments read by the post-processing passes.
+pub const HDR: &str = "hdr";
+pub const BLOOM_A: &str = "bloom_a";
+pub const BLOOM_B: &str = "bloom_b";
+pub const LDR: &str = "ldr";
+
+const FLAG_TONE_MAPPING: u32 = 1;
+const FLAG_BLOOM: u32 = 2;
+const FLAG_COLOR_GRADING: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    contrast: f32,
+    saturation: f32,
+    flags: u32,
+    _padding: [u32; 2],
+    tint: [f32; 4],
+}
+
+impl PostUniform {
+    fn new(settings: &GraphicsSettings) -> Self {
+        let flags = [
+            (settings.tone_mapping, FLAG_TONE_MAPPING),
+            (settings.bloom, FLAG_BLOOM),
+            (settings.color_grading, FLAG_COLOR_GRADING),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |flags, (_, flag)| flags | flag);
+        let [r, g, b] = settings.tint;
+        Self {
+            exposure: settings.exposure,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+            contrast: settings.contrast,
+            saturation: settings.saturation,
+            flags,
+            _padding: [0; 2],
+            tint: [r, g, b, 1.0],
+        }
+    }
+}
+
+/// The fullscreen passes run after the scene, in order.
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    /// Downsamples the bright parts of the scene into the bloom target.
+    BloomBright,
+    BloomBlurX,
+    BloomBlurY,
+    /// Adds bloom, tone maps and grades the scene.
+    Composite,
+    Fxaa,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [Stage::BloomBright, Stage::BloomBlurX, Stage::BloomBlurY, Stage::Composite, Stage::Fxaa];
+
+    /// Attachments bound as the main and the secondary input.
+    fn inputs(self) -> (&'static str, &'static str) {
+        match self {
+            Stage::BloomBright => (HDR, HDR),
+            Stage::BloomBlurX => (BLOOM_A, BLOOM_A),
+            Stage::BloomBlurY => (BLOOM_B, BLOOM_B),
+            Stage::Composite => (HDR, BLOOM_A),
+            Stage::Fxaa => (LDR, LDR),
+        }
+    }
+}
+
+/// Pipelines and bindings for the post-processing chain. The bind groups
+/// point at graph attachments, so they are rebuilt with [`PostProcess::bind`]
+/// whenever the graph recreates them.
+pub struct PostProcess {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    pipelines: [wgpu::RenderPipeline; 5],
+    bind_groups: [Option<wgpu::BindGroup>; 5],
+}
+
+impl PostProcess {
+    /// The composite writes to the LDR attachment when FXAA follows it and to
+    /// the surface otherwise.
+    pub fn new(device: &wgpu::Device, settings: &GraphicsSettings, surface_format: wgpu::TextureFormat) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[PostUniform::new(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let composite_format = if settings.fxaa { LDR_FORMAT } else { surface_format };
+        let pipelines = Stage::ALL.map(|stage| {
+            let (entry_point, format) = match stage {
+                Stage::BloomBright => ("fs_bloom_bright", HDR_FORMAT),
+                Stage::BloomBlurX => ("fs_blur_x", HDR_FORMAT),
+                Stage::BloomBlurY => ("fs_blur_y", HDR_FORMAT),
+                Stage::Composite => ("fs_composite", composite_format),
+                Stage::Fxaa => ("fs_fxaa", surface_format),
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        Self {
+            layout,
+            sampler,
+            uniform_buffer,
+            pipelines,
+            bind_groups: Default::default(),
+        }
+    }
+
+    pub fn update_settings(&self, queue: &wgpu::Queue, settings: &GraphicsSettings) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[PostUniform::new(settings)]));
+    }
+
+    /// Binds each stage's inputs from `graph`. Stages whose inputs the graph
+    /// doesn't have are left unbound and draw nothing.
+    pub fn bind<C>(&mut self, device: &wgpu::Device, graph: &RenderGraph<C>) {
+        self.bind_groups = Stage::ALL.map(|stage| {
+            let (input, secondary) = stage.inputs();
+            let (input, secondary) = (graph.view(input)?, graph.view(secondary)?);
+            Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post_bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(input) },
+                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(secondary) },
+                    wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                    wgpu::BindGroupEntry { binding: 3, resource: self.uniform_buffer.as_entire_binding() },
+                ],
+            }))
+        });
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, stage: Stage) {
+        let Some(bind_group) = &self.bind_groups[stage as usize] else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipelines[stage as usize]);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Name under which passes refer to the swapchain texture of the frame.
+pub const SURFACE: &str = "surface";
+
+/// An intermediate texture owned by the graph and recreated on resize.
+pub struct AttachmentDesc {
+    pub format: wgpu::TextureFormat,
+    /// Size relative to the surface.
+    pub scale: f32,
+    /// Usages besides `RENDER_ATTACHMENT`, e.g. `TEXTURE_BINDING` to sample
+    /// it in a later pass.
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+struct Attachment {
+    desc: AttachmentDesc,
+    view: wgpu::TextureView,
+}
+
+pub struct ColorTarget {
+    pub attachment: &'static str,
+    /// Single-sampled attachment a multisampled `attachment` resolves into.
+    pub resolve: Option<&'static str>,
+    pub ops: wgpu::Operations<wgpu::Color>,
+}
+
+pub struct DepthTarget {
+    pub attachment: &'static str,
+    /// `None` binds the attachment read-only, which lets the pass sample it
+    /// as well.
+    pub ops: Option<wgpu::Operations<f32>>,
+}
+
+/// What a pass renders to and how each target is loaded and stored.
+#[derive(Default)]
+pub struct PassDesc {
+    pub color: Vec<ColorTarget>,
+    pub depth: Option<DepthTarget>,
+}
+
+type Record<C> = Box<dyn Fn(&C, &mut wgpu::RenderPass<'_>)>;
+
+struct Pass<C> {
+    name: &'static str,
+    desc: PassDesc,
+    enabled: bool,
+    record: Record<C>,
+}
+
+/// Render passes run in the order they were added, each recording its draws
+/// from a shared context `C`. Disabled passes are skipped; if that leaves a
+/// later pass loading a target nothing has written this frame, the target is
+/// cleared instead.
+pub struct RenderGraph<C> {
+    width: u32,
+    height: u32,
+    attachments: HashMap<&'static str, Attachment>,
+    passes: Vec<Pass<C>>,
+}
+
+impl<C> RenderGraph<C> {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, attachments: HashMap::new(), passes: Vec::new() }
+    }
+
+    pub fn add_attachment(&mut self, device: &wgpu::Device, name: &'static str, desc: AttachmentDesc) {
+        let view = Self::create_view(device, name, &desc, self.width, self.height);
+        self.attachments.insert(name, Attachment { desc, view });
+    }
+
+    /// Appends a pass. Fails if it targets an attachment that hasn't been
+    /// added.
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        desc: PassDesc,
+        record: impl Fn(&C, &mut wgpu::RenderPass<'_>) + 'static,
+    ) -> Result<()> {
+        let targets = desc
+            .color
+            .iter()
+            .flat_map(|c| [Some(c.attachment), c.resolve])
+            .flatten()
+            .chain(desc.depth.as_ref().map(|d| d.attachment));
+        for target in targets {
+            if target != SURFACE && !self.attachments.contains_key(target) {
+                bail!("pass '{}' targets unknown attachment '{}'", name, target);
+            }
+        }
+        self.passes.push(Pass { name, desc, enabled: true, record: Box::new(record) });
+        Ok(())
+    }
+
+    /// Recreates every attachment for the new surface size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        for (name, attachment) in &mut self.attachments {
+            attachment.view = Self::create_view(device, name, &attachment.desc, width, height);
+        }
+    }
+
+    pub fn view(&self, attachment: &str) -> Option<&wgpu::TextureView> {
+        self.attachments.get(attachment).map(|a| &a.view)
+    }
+
+    pub fn is_enabled(&self, pass: &str) -> bool {
+        self.passes.iter().any(|p| p.name == pass && p.enabled)
+    }
+
+    /// Returns false if there is no pass called `pass`.
+    pub fn set_enabled(&mut self, pass: &str, enabled: bool) -> bool {
+        match self.passes.iter_mut().find(|p| p.name == pass) {
+            Some(p) => {
+                p.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, surface: &wgpu::TextureView, context: &C) {
+        let mut written = HashSet::new();
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            let view = |name: &str| if name == SURFACE { surface } else { &self.attachments[name].view };
+
+            let color_attachments: Vec<_> = pass
+                .desc
+                .color
+                .iter()
+                .map(|target| {
+                    let mut ops = target.ops;
+                    if !written.contains(target.attachment) && matches!(ops.load, wgpu::LoadOp::Load) {
+                        ops.load = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+                    }
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: view(target.attachment),
+                        resolve_target: target.resolve.map(view),
+                        ops,
+                        depth_slice: None,
+                    })
+                })
+                .collect();
+            let depth_stencil_attachment = pass.desc.depth.as_ref().map(|target| {
+                let mut ops = target.ops;
+                if let Some(ops) = &mut ops
+                    && !written.contains(target.attachment)
+                    && matches!(ops.load, wgpu::LoadOp::Load)
+                {
+                    ops.load = wgpu::LoadOp::Clear(1.0);
+                }
+                wgpu::RenderPassDepthStencilAttachment {
+                    view: view(target.attachment),
+                    depth_ops: ops,
+                    stencil_ops: None,
+                }
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(pass.name),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            (pass.record)(context, &mut render_pass);
+
+            written.extend(pass.desc.color.iter().flat_map(|target| [Some(target.attachment), target.resolve]).flatten());
+            written.extend(pass.desc.depth.as_ref().filter(|target| target.ops.is_some()).map(|target| target.attachment));
+        }
+    }
+
+    fn create_view(
+        device: &wgpu::Device,
+        name: &str,
+        desc: &AttachmentDesc,
+        width: u32,
+        height: u32,
+    ) -> wgpu::TextureView {
+        let scaled = |size: u32| ((size as f32 * desc.scale) as u32).max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width: scaled(width),
+                height: scaled(height),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: desc.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | desc.usage,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
@@ -1,52 +1,237 @@
-use crate::camera::{OsrsCamera, Projection};
+use crate::assets::{AssetManager, Handle, MaterialSlots};
+use crate::camera::{CameraMode, OsrsCamera, Projection};
 use crate::camera_controller::CameraController;
-use crate::model::{self, InstanceRaw, Model, Vertex};
-use crate::player::Player;
-use crate::world::World;
+use crate::context_menu::{self, ContextMenu, MenuAction};
+use crate::culling::{CullStats, Frustum};
+use crate::entity::{self, Entity, EntityId, EntityKind, Interaction, INTERACTION_RANGE};
+use crate::gamepad::{GamepadAxes, Gamepads};
+use crate::input::{Action, InputMap};
+use crate::instances::{self, Batch, InstanceArena};
+use crate::lod::{self, Lod};
+#[cfg(debug_assertions)]
+use crate::hot_reload::{self, HotReloader};
+use crate::model::{self, AlphaPass, Drawable, InstanceRaw, MaterialParams, MeshData, Model, Vertex};
+use crate::overlay::{self, ClickMarker, Overlay};
+use crate::player::{Player, WALK_SPEED};
+use crate::post_process::{self, PostProcess, Stage};
+use crate::render_graph::{self, AttachmentDesc, ColorTarget, DepthTarget, PassDesc, RenderGraph};
+use crate::sky::Sky;
+use crate::settings::{GraphicsSettings, Settings, SETTINGS_FILE};
+use crate::terrain::Terrain;
+use crate::water::Water;
+use crate::world::{self, World};
 use anyhow::Result;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalPosition;
 use winit::event::WindowEvent;
 use winit::window::Window;
 
+/// The scene's depth buffer in the render graph.
+pub const DEPTH_ATTACHMENT: &str = "depth";
+/// Tint of the tiles on the player's path when `show_path` is on.
+const PATH_COLOR: [f32; 4] = [0.2, 0.8, 1.0, 0.3];
+const GRID_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.35];
+/// How many tiles around the camera the grid reaches.
+const GRID_RADIUS: usize = 24;
+const HOVERED_TILE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.7];
+const PLAYER_TILE_COLOR: [f32; 4] = [0.2, 0.9, 1.0, 0.9];
+const DESTINATION_TILE_COLOR: [f32; 4] = [1.0, 0.9, 0.0, 0.9];
+/// Raw mouse movement while the menu button is held beyond which it turns
+/// the camera instead of opening the menu on release.
+const MENU_DRAG_THRESHOLD: f64 = 4.0;
+/// The debug actions and the render pass each switches, by name so that a
+/// key keeps its pass when settings add or remove others.
+const PASS_TOGGLES: [(Action, &str); 8] = [
+    (Action::ToggleSkyPass, "sky"),
+    (Action::ToggleOpaquePass, "opaque"),
+    (Action::ToggleTransparentPass, "transparent"),
+    (Action::ToggleWaterPass, "water"),
+    (Action::ToggleOverlayPass, "overlay"),
+    (Action::ToggleBloomBrightPass, "bloom_bright"),
+    (Action::ToggleBloomBlurXPass, "bloom_blur_x"),
+    (Action::ToggleBloomBlurYPass, "bloom_blur_y"),
+];
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    view_position: [f32; 4],
+    fog_color: [f32; 3],
+    fog_density: f32,
+    /// Distance at which the fog becomes opaque whatever its density, so
+    /// geometry never pops at the far plane.
+    fog_end: f32,
+    _padding: [f32; 3],
 }
 
 impl CameraUniform {
     fn new() -> Self {
         Self {
             view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            view_position: [0.0; 4],
+            fog_color: [0.0; 3],
+            fog_density: 0.0,
+            fog_end: f32::MAX,
+            _padding: [0.0; 3],
         }
     }
+    fn update_fog(&mut self, graphics: &GraphicsSettings, projection: &Projection) {
+        self.fog_color = graphics.fog_color;
+        self.fog_density = graphics.fog_density;
+        self.fog_end = projection.zfar();
+    }
     fn update_view_proj(&mut self, camera: &OsrsCamera, projection: &Projection) {
         self.view_proj =
             (projection.build_projection_matrix() * camera.build_view_matrix()).to_cols_array_2d();
+        self.view_position = camera.eye_position().extend(1.0).to_array();
+    }
+}
+
+/// One pipeline per combination of blending and face culling a material can ask for.
+pub struct MaterialPipelines {
+    opaque: wgpu::RenderPipeline,
+    opaque_double_sided: wgpu::RenderPipeline,
+    blend: wgpu::RenderPipeline,
+    blend_double_sided: wgpu::RenderPipeline,
+}
+
+impl MaterialPipelines {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> Self {
+        let create = |label: &str, blended: bool, double_sided: bool| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: post_process::HDR_FORMAT,
+                        blend: Some(if blended {
+                            wgpu::BlendState::ALPHA_BLENDING
+                        } else {
+                            wgpu::BlendState::REPLACE
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: if double_sided { None } else { Some(wgpu::Face::Back) },
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: !blended,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        Self {
+            opaque: create("Opaque Pipeline", false, false),
+            opaque_double_sided: create("Opaque Double Sided Pipeline", false, true),
+            blend: create("Blend Pipeline", true, false),
+            blend_double_sided: create("Blend Double Sided Pipeline", true, true),
+        }
+    }
+
+    pub fn select(&self, params: &MaterialParams) -> &wgpu::RenderPipeline {
+        let blended = params.alpha_mode == model::AlphaMode::Blend;
+        match (blended, params.double_sided) {
+            (false, false) => &self.opaque,
+            (false, true) => &self.opaque_double_sided,
+            (true, false) => &self.blend,
+            (true, true) => &self.blend_double_sided,
+        }
     }
 }
 
+/// What the scene passes draw this frame, gathered before the graph runs.
+#[derive(Default)]
+struct FrameData {
+    terrain_chunks: Vec<usize>,
+    terrain_instances: Range<u32>,
+    batches: Vec<Batch>,
+}
+
 pub struct State {
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: MaterialPipelines,
+    settings: Settings,
+    post: PostProcess,
     camera: OsrsCamera,
     projection: Projection,
     camera_controller: CameraController,
+    input: InputMap,
+    gamepads: Gamepads,
+    cursor: PhysicalPosition<f64>,
+    cursor_in_window: bool,
+    focused: bool,
+    /// Whether the left stick moves the cursor instead of the player.
+    virtual_cursor: bool,
+    /// Where the virtual cursor moved the cursor to, for the window to follow.
+    cursor_warp: Option<PhysicalPosition<f64>>,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
-    depth_view: wgpu::TextureView,
+    graph: RenderGraph<State>,
+    frame: FrameData,
     player: Player,
     world: World,
-    landscape_model: Model,
-    player_model: Model,
-    player_instance_buffer: wgpu::Buffer,
+    assets: AssetManager,
+    terrain: Terrain,
+    water: Water,
+    sky: Sky,
+    overlay: Overlay,
+    click_marker: Option<ClickMarker>,
+    /// The tile under the cursor, if it points at the ground.
+    hovered_tile: Option<(usize, usize)>,
+    player_model: Handle<Model>,
+    player_lod: Lod,
+    entities: Vec<Entity>,
+    menu: Option<ContextMenu>,
+    /// Where the menu button went down and how far the mouse has moved since,
+    /// while it is held.
+    menu_press: Option<(PhysicalPosition<f64>, f64)>,
+    /// What the player does on reaching the entity it is walking to.
+    pending_interaction: Option<(EntityId, Interaction)>,
+    instances: InstanceArena,
+    /// Meshes drawn and culled in the last frame, for profiling.
+    cull_stats: CullStats,
+    last_update: Instant,
+    #[cfg(debug_assertions)]
+    hot_reloader: Option<HotReloader>,
 }
 
 impl State {
@@ -61,10 +246,16 @@ impl State {
             .request_adapter(&wgpu::RequestAdapterOptions::default())
             .await
             .unwrap();
+        // Compressed texture formats are used when the GPU has them; KTX2
+        // textures fall back to CPU decoding otherwise.
+        let compression = wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC;
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("Device"),
+                    required_features: adapter.features() & compression,
                     ..Default::default()
                 },
             )
@@ -101,7 +292,7 @@ impl State {
         let player = Player::new(Vec3::new(32.0, 0.0, 32.0));
         let camera = OsrsCamera::new(player.position);
         let projection = Projection::new(config.width, config.height, 45.0, 0.5, 500.0);
-        let camera_controller = CameraController::new(2.0, 0.2);
+        let camera_controller = CameraController::default();
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
 
@@ -114,7 +305,7 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -133,41 +324,41 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let depth_view = Self::create_depth_view(&device, &config);
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let mut assets = AssetManager::new(&device, &queue);
+        let settings = Settings::load(&assets.resolve(SETTINGS_FILE));
+        let input = InputMap::new(settings.bindings.clone());
+        let graph = Self::create_graph(&device, &config, &settings.graphics)?;
+        let mut post = PostProcess::new(&device, &settings.graphics, config.format);
+        post.bind(&device, &graph);
 
-        let landscape_model = Model::from_heightmap(&device, &queue, &world, &texture_bind_group_layout)?;
-        let player_model: Model = model::load_gltf(&device, &queue, "res/character.glb")?;
+        let terrain = Terrain::new(&mut assets, &world);
+        let mut water = Water::new(&device, &world, &camera_bind_group_layout, msaa_samples(&settings.graphics));
+        water.bind(&device, &graph);
+        let sky = Sky::new(&device, msaa_samples(&settings.graphics));
+        let overlay = Overlay::new(&device, &camera_bind_group_layout, msaa_samples(&settings.graphics));
+        let player_model = assets.load_model_async("character.glb");
+        let entities = vec![
+            Entity {
+                name: "Guide",
+                kind: EntityKind::Npc,
+                examine: "Helps new adventurers find their way.",
+                model: assets.retain_model(player_model),
+                position: Vec3::new(36.0, world.surface_height(36.0, 30.0), 30.0),
+                model_transform: character_model_transform(),
+                lod: Lod::default(),
+            },
+            Entity {
+                name: "Tree",
+                kind: EntityKind::Tree,
+                examine: "One of the most common trees around.",
+                model: tree_model(&mut assets),
+                position: Vec3::new(26.0, world.surface_height(26.0, 38.0), 38.0),
+                model_transform: Mat4::IDENTITY,
+                lod: Lod::default(),
+            },
+        ];
 
-        let player_instance_data = InstanceRaw { model: Mat4::IDENTITY.to_cols_array_2d() };
-        let player_instance_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Player Instance Buffer"),
-                contents: bytemuck::cast_slice(&[player_instance_data]),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            });
+        let instances = InstanceArena::new(&device);
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
@@ -177,44 +368,21 @@ impl State {
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &texture_bind_group_layout],
+                bind_group_layouts: &[&camera_bind_group_layout, assets.material_bind_group_layout()],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[Vertex::desc(), InstanceRaw::desc()],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                cull_mode: Some(wgpu::Face::Back),
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        let pipelines = MaterialPipelines::new(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            msaa_samples(&settings.graphics),
+        );
+
+        #[cfg(debug_assertions)]
+        let hot_reloader = HotReloader::new(assets.root())
+            .inspect_err(|e| log::warn!("asset hot reloading disabled: {}", e))
+            .ok();
 
         Ok(Self {
             surface,
@@ -222,41 +390,176 @@ impl State {
             queue,
             config,
             size,
-            render_pipeline,
+            shader,
+            pipeline_layout: render_pipeline_layout,
+            pipelines,
+            settings,
+            post,
             camera,
             projection,
             camera_controller,
+            input,
+            gamepads: Gamepads::new(),
+            cursor: PhysicalPosition::default(),
+            cursor_in_window: false,
+            focused: true,
+            virtual_cursor: false,
+            cursor_warp: None,
             camera_uniform,
             camera_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
-            depth_view,
+            graph,
+            frame: FrameData::default(),
             player,
             world,
-            landscape_model,
+            assets,
+            terrain,
+            water,
+            sky,
+            overlay,
+            click_marker: None,
+            hovered_tile: None,
             player_model,
-            player_instance_buffer,
+            player_lod: Lod::default(),
+            entities,
+            menu: None,
+            menu_press: None,
+            pending_interaction: None,
+            instances,
+            cull_stats: CullStats::default(),
+            last_update: Instant::now(),
+            #[cfg(debug_assertions)]
+            hot_reloader,
         })
     }
 
-    fn create_depth_view(
+    /// The scene is drawn in HDR, the sky first, then opaque geometry and
+    /// blended geometry over it, then the water and the overlay on top
+    /// without writing depth, then bloomed, tone mapped and graded onto the
+    /// surface, with FXAA as the last step when enabled.
+    fn create_graph(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
-    ) -> wgpu::TextureView {
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth Texture"),
-            size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
-        depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
+        graphics: &GraphicsSettings,
+    ) -> Result<RenderGraph<State>> {
+        let samples = msaa_samples(graphics);
+        let mut graph = RenderGraph::new(config.width, config.height);
+        let mut attachment = |name, format, scale, usage, sample_count| {
+            graph.add_attachment(device, name, AttachmentDesc { format, scale, usage, sample_count });
+        };
+        let sampled = wgpu::TextureUsages::TEXTURE_BINDING;
+        attachment(post_process::HDR, post_process::HDR_FORMAT, 1.0, sampled, 1);
+        if samples > 1 {
+            attachment("hdr_msaa", post_process::HDR_FORMAT, 1.0, wgpu::TextureUsages::empty(), samples);
+        }
+        attachment(DEPTH_ATTACHMENT, wgpu::TextureFormat::Depth32Float, 1.0, sampled, samples);
+        attachment(post_process::BLOOM_A, post_process::HDR_FORMAT, 0.5, sampled, 1);
+        attachment(post_process::BLOOM_B, post_process::HDR_FORMAT, 0.5, sampled, 1);
+        if graphics.fxaa {
+            attachment(post_process::LDR, post_process::LDR_FORMAT, 1.0, sampled, 1);
+        }
+
+        let scene_color = |load| {
+            let (attachment, resolve) = if samples > 1 {
+                ("hdr_msaa", Some(post_process::HDR))
+            } else {
+                (post_process::HDR, None)
+            };
+            ColorTarget { attachment, resolve, ops: wgpu::Operations { load, store: wgpu::StoreOp::Store } }
+        };
+        let scene_pass = |depth_load| PassDesc {
+            color: vec![scene_color(wgpu::LoadOp::Load)],
+            depth: Some(DepthTarget {
+                attachment: DEPTH_ATTACHMENT,
+                ops: Some(wgpu::Operations { load: depth_load, store: wgpu::StoreOp::Store }),
+            }),
+        };
+        graph.add_pass(
+            "sky",
+            PassDesc { color: vec![scene_color(wgpu::LoadOp::Clear(wgpu::Color::BLACK))], depth: None },
+            |state: &State, render_pass| state.sky.draw(render_pass),
+        )?;
+        graph.add_pass(
+            "opaque",
+            scene_pass(wgpu::LoadOp::Clear(1.0)),
+            |state: &State, render_pass| state.draw_scene(render_pass, AlphaPass::Opaque),
+        )?;
+        graph.add_pass(
+            "transparent",
+            scene_pass(wgpu::LoadOp::Load),
+            |state: &State, render_pass| state.draw_scene(render_pass, AlphaPass::Blend),
+        )?;
+        // Tested against the scene's depth without writing it, which lets the
+        // water sample it in the same pass.
+        let read_depth_pass = || PassDesc {
+            color: vec![scene_color(wgpu::LoadOp::Load)],
+            depth: Some(DepthTarget { attachment: DEPTH_ATTACHMENT, ops: None }),
+        };
+        graph.add_pass(
+            "water",
+            read_depth_pass(),
+            |state: &State, render_pass| state.water.draw(render_pass, &state.camera_bind_group),
+        )?;
+        graph.add_pass(
+            "overlay",
+            read_depth_pass(),
+            |state: &State, render_pass| state.overlay.draw(render_pass, &state.camera_bind_group),
+        )?;
+
+        let fullscreen = |attachment| PassDesc {
+            color: vec![ColorTarget {
+                attachment,
+                resolve: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: wgpu::StoreOp::Store },
+            }],
+            depth: None,
+        };
+        let mut post_pass = |name, target, stage| {
+            graph.add_pass(name, fullscreen(target), move |state: &State, render_pass| {
+                state.post.draw(render_pass, stage)
+            })
+        };
+        if graphics.bloom {
+            post_pass("bloom_bright", post_process::BLOOM_A, Stage::BloomBright)?;
+            post_pass("bloom_blur_x", post_process::BLOOM_B, Stage::BloomBlurX)?;
+            post_pass("bloom_blur_y", post_process::BLOOM_A, Stage::BloomBlurY)?;
+        }
+        if graphics.fxaa {
+            post_pass("composite", post_process::LDR, Stage::Composite)?;
+            post_pass("fxaa", render_graph::SURFACE, Stage::Fxaa)?;
+        } else {
+            post_pass("composite", render_graph::SURFACE, Stage::Composite)?;
+        }
+        Ok(graph)
+    }
+
+    /// Switches effects on or off and applies changed parameters. Only
+    /// changes to MSAA, FXAA or bloom rebuild the graph; everything else is a
+    /// uniform update.
+    fn apply_graphics_settings(&mut self, graphics: GraphicsSettings) -> Result<()> {
+        let current = &self.settings.graphics;
+        let structural = msaa_samples(&graphics) != msaa_samples(current)
+            || graphics.fxaa != current.fxaa
+            || graphics.bloom != current.bloom;
+        if structural {
+            let graph = Self::create_graph(&self.device, &self.config, &graphics)?;
+            let samples = msaa_samples(&graphics);
+            if samples != msaa_samples(current) {
+                self.pipelines = MaterialPipelines::new(&self.device, &self.pipeline_layout, &self.shader, samples);
+                self.water = Water::new(&self.device, &self.world, &self.camera_bind_group_layout, samples);
+                self.sky = Sky::new(&self.device, samples);
+                self.overlay = Overlay::new(&self.device, &self.camera_bind_group_layout, samples);
+            }
+            self.post = PostProcess::new(&self.device, &graphics, self.config.format);
+            self.post.bind(&self.device, &graph);
+            self.water.bind(&self.device, &graph);
+            self.graph = graph;
+        } else {
+            self.post.update_settings(&self.queue, &graphics);
+        }
+        self.settings.graphics = graphics;
+        Ok(())
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -266,7 +569,9 @@ impl State {
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
             self.projection.resize(new_size.width, new_size.height);
-            self.depth_view = Self::create_depth_view(&self.device, &self.config);
+            self.graph.resize(&self.device, new_size.width, new_size.height);
+            self.post.bind(&self.device, &self.graph);
+            self.water.bind(&self.device, &self.graph);
         }
     }
 
@@ -274,68 +579,401 @@ impl State {
         self.size
     }
 
+    /// Feeds a window event to the action bindings. Returns whether it was
+    /// bound to anything.
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = *position;
+                self.cursor_in_window = true;
+            }
+            WindowEvent::CursorEntered { .. } => self.cursor_in_window = true,
+            WindowEvent::CursorLeft { .. } => self.cursor_in_window = false,
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                if !focused {
+                    self.input.release_all();
+                    self.menu_press = None;
+                }
+            }
+            _ => {}
+        }
+        self.input.process_event(event)
+    }
+
+    /// Consumes the pending presses of `action`, returning whether there were
+    /// any.
+    pub fn take_action(&mut self, action: Action) -> bool {
+        self.input.take_pressed(action)
     }
 
+    /// Switches the render pass called `name` on or off, for debugging.
+    fn toggle_pass(&mut self, name: &str) {
+        let enabled = !self.graph.is_enabled(name);
+        if self.graph.set_enabled(name, enabled) {
+            log::info!("{} pass {}", name, if enabled { "enabled" } else { "disabled" });
+        } else {
+            log::info!("there is no {} pass with the current graphics settings", name);
+        }
+    }
+
+    /// Raw mouse movement, which arrives wherever the cursor is, so it is
+    /// dropped while another window has focus.
     pub fn mouse_motion(&mut self, delta: (f64, f64)) {
-        self.camera_controller.process_mouse_motion(delta.0, delta.1);
+        if self.focused {
+            self.camera_controller.process_mouse_motion(delta.0, delta.1);
+            if let Some((_, moved)) = &mut self.menu_press {
+                *moved += delta.0.hypot(delta.1);
+            }
+        }
     }
 
-    pub fn set_player_destination(&mut self, cursor_pos: winit::dpi::PhysicalPosition<f64>) {
-        let ndc_x = (2.0 * cursor_pos.x as f32) / self.size.width as f32 - 1.0;
-        let ndc_y = 1.0 - (2.0 * cursor_pos.y as f32) / self.size.height as f32;
+    /// Whether the cursor should be locked and hidden, which it is while the
+    /// mouse turns the camera.
+    pub fn wants_cursor_grab(&self) -> bool {
+        self.focused && (self.camera.mode() != CameraMode::Orbit || self.input.is_held(Action::RotateDrag))
+    }
+
+    /// Where the virtual cursor moved to since the last call, if it moved.
+    pub fn take_cursor_warp(&mut self) -> Option<PhysicalPosition<f64>> {
+        self.cursor_warp.take()
+    }
+
+    /// The menu options for what is under `cursor_pos`. Entities behind
+    /// terrain are hidden by it, and water can't be walked to.
+    fn menu_options(&self, cursor_pos: PhysicalPosition<f64>) -> Vec<context_menu::MenuOption> {
+        let (origin, direction) = self.cursor_ray(cursor_pos);
+        let hit = self.world.raycast(origin, direction, self.projection.zfar());
+        let max_distance = hit.map_or(self.projection.zfar(), |hit| origin.distance(hit.point));
+        let picked = entity::pick(&self.entities, &self.assets, origin, direction, max_distance);
+        let ground = hit.filter(|hit| !self.world.is_water(hit.tile.0, hit.tile.1)).map(|hit| hit.point);
+        context_menu::options(picked.map(|(id, _)| (id, &self.entities[id])), ground)
+    }
 
-        let proj_matrix = self.projection.build_projection_matrix();
-        let view_matrix = self.camera.build_view_matrix();
-        let inv_proj = proj_matrix.inverse();
-        let inv_view = view_matrix.inverse();
-
-        let clip_coords = glam::Vec4::new(ndc_x, ndc_y, -1.0, 1.0);
-        let mut eye_coords = inv_proj * clip_coords;
-        eye_coords.z = -1.0;
-        eye_coords.w = 0.0;
-
-        let world_coords_vec = inv_view * eye_coords;
-        let ray_direction = world_coords_vec.truncate().normalize();
-        let ray_origin = self.camera.eye_position();
-
-        if ray_direction.y.abs() > 0.001 {
-            let t = (0.0 - ray_origin.y) / ray_direction.y;
-            if t > 0.0 {
-                let intersection_point = ray_origin + t * ray_direction;
-                self.player.target_position = Some(intersection_point);
+    fn perform(&mut self, action: MenuAction) {
+        match action {
+            MenuAction::WalkHere(point) => {
+                self.player.target_position = Some(point);
+                self.pending_interaction = None;
+                self.click_marker = Some(ClickMarker::new(point, overlay::WALK_MARKER_COLOR));
             }
+            MenuAction::Interact(id, interaction) if interaction.needs_reach() => {
+                // Stop next to the entity, on the side the player comes from.
+                let entity = &self.entities[id];
+                let away = (self.player.position - entity.position).with_y(0.0).normalize_or_zero();
+                self.player.target_position = Some(entity.position + away * INTERACTION_RANGE * 0.5);
+                self.pending_interaction = Some((id, interaction));
+                self.click_marker = Some(ClickMarker::new(entity.position, overlay::INTERACT_MARKER_COLOR));
+            }
+            MenuAction::Interact(id, _) => log::info!("{}", self.entities[id].examine),
+            MenuAction::Cancel => {}
+        }
+    }
+
+    /// Finds the ground tile under the cursor, logging its corner heights
+    /// when it changes to help with editing the world.
+    fn update_hovered_tile(&mut self) {
+        let tile = (self.focused && self.cursor_in_window)
+            .then(|| {
+                let (origin, direction) = self.cursor_ray(self.cursor);
+                self.world.raycast(origin, direction, self.projection.zfar())
+            })
+            .flatten()
+            .map(|hit| hit.tile);
+        if tile != self.hovered_tile
+            && let Some((x, z)) = tile
+        {
+            let heights = &self.world.heightmap;
+            log::debug!(
+                "tile ({}, {}), corner heights {:.2} {:.2} {:.2} {:.2}",
+                x,
+                z,
+                heights[x][z],
+                heights[x + 1][z],
+                heights[x][z + 1],
+                heights[x + 1][z + 1]
+            );
         }
+        self.hovered_tile = tile;
+    }
+
+    /// Carries out the pending interaction once the player has stopped, if it
+    /// stopped within reach.
+    fn finish_interaction(&mut self) {
+        if self.player.target_position.is_some() {
+            return;
+        }
+        let Some((id, interaction)) = self.pending_interaction.take() else {
+            return;
+        };
+        let entity = &self.entities[id];
+        if (entity.position - self.player.position).with_y(0.0).length() <= INTERACTION_RANGE {
+            log::info!("{} {} is not implemented yet", interaction.label(), entity.name);
+        } else {
+            log::info!("I can't reach that.");
+        }
+    }
+
+    /// The ray through `cursor_pos` from the near plane, as origin and
+    /// direction.
+    fn cursor_ray(&self, cursor_pos: PhysicalPosition<f64>) -> (Vec3, Vec3) {
+        let ndc_x = (2.0 * cursor_pos.x as f32) / self.size.width as f32 - 1.0;
+        let ndc_y = 1.0 - (2.0 * cursor_pos.y as f32) / self.size.height as f32;
+        let view_proj = self.projection.build_projection_matrix() * self.camera.build_view_matrix();
+        let inverse = view_proj.inverse();
+        let near = inverse.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inverse.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+        (near, (far - near).normalize())
     }
 
     pub fn update(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        #[cfg(debug_assertions)]
+        self.poll_hot_reload();
+        self.assets.poll_loads();
+
+        let axes = self.gamepads.poll(&mut self.input, &self.settings.gamepad);
+        if self.input.take_pressed(Action::ToggleVirtualCursor) {
+            self.virtual_cursor = !self.virtual_cursor;
+            log::info!("virtual cursor {}", if self.virtual_cursor { "on" } else { "off" });
+        }
+        self.apply_gamepad(axes, dt);
+        self.update_menu();
+        self.update_hovered_tile();
+
         if let Some(target) = self.player.target_position {
             let direction = target - self.player.position;
             let distance = direction.length();
-            let speed = 0.2;
+            let speed = WALK_SPEED * dt;
 
             if distance < speed {
                 self.player.position = target;
                 self.player.target_position = None;
             } else {
-                self.player.position += direction.normalize() * speed;
+                let next = self.player.position + direction.normalize() * speed;
+                if self.world.is_walkable(next) {
+                    self.player.position = next;
+                } else {
+                    self.player.target_position = None;
+                }
             }
         }
 
-        let player_x = self.player.position.x;
-        let player_z = self.player.position.z;
-        self.player.position.y = self.world.get_height(player_x, player_z);
+        self.finish_interaction();
+
+        let player = self.player.position;
+        self.player.position.y = self.world.surface_height(player.x, player.z);
 
         self.camera.focus_point = self.player.position;
-        self.camera_controller.update_camera(&mut self.camera);
+        let edge_cursor = (self.focused && self.cursor_in_window && self.menu.is_none())
+            .then(|| Vec2::new(self.cursor.x as f32, self.cursor.y as f32));
+        let size = Vec2::new(self.size.width as f32, self.size.height as f32);
+        self.camera_controller.process_cursor(edge_cursor, size, self.settings.camera.edge_margin);
+        self.camera_controller.update_camera(&mut self.camera, &mut self.input, &self.settings.camera, dt);
+        self.camera.update(dt, &self.world);
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
+        self.camera_uniform.update_fog(&self.settings.graphics, &self.projection);
+
+        let eye = self.camera.eye_position();
+        self.terrain.update_lod(eye, self.projection.fovy());
+        if let Some(model) = self.assets.model(self.player_model) {
+            let bounds = model.bounds.transformed(&self.player_transform());
+            let size = lod::screen_size(&bounds, eye, self.projection.fovy());
+            self.player_lod.update(size, &lod::MODEL_LOD_THRESHOLDS, model.lod_count - 1);
+        }
+        for entity in &mut self.entities {
+            if let Some(model) = self.assets.model(entity.model) {
+                let bounds = model.bounds.transformed(&entity.transform());
+                let size = lod::screen_size(&bounds, eye, self.projection.fovy());
+                entity.lod.update(size, &lod::MODEL_LOD_THRESHOLDS, model.lod_count - 1);
+            }
+        }
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+
+        if self.input.take_pressed(Action::ReloadSettings) {
+            self.reload_settings();
+        }
+        if self.input.take_pressed(Action::RebindControls) {
+            self.input.begin_rebind();
+        }
+        for (action, pass) in PASS_TOGGLES {
+            if self.input.take_pressed(action) {
+                self.toggle_pass(pass);
+            }
+        }
+        if self.input.take_pressed(Action::OpenInventory) {
+            log::info!("inventory is not implemented yet");
+        }
+        let graphics = &mut self.settings.graphics;
+        if self.input.take_pressed(Action::ToggleGrid) {
+            graphics.show_grid = !graphics.show_grid;
+            log::info!("tile grid {}", if graphics.show_grid { "on" } else { "off" });
+        }
+        if self.input.take_pressed(Action::ToggleTileHighlights) {
+            graphics.tile_highlights = !graphics.tile_highlights;
+            log::info!("tile highlights {}", if graphics.tile_highlights { "on" } else { "off" });
+        }
+        if self.input.take_rebound() {
+            self.save_settings();
+        }
+        self.input.end_frame();
+    }
+
+    /// Left-click does the default action under the cursor, or picks from
+    /// the open menu; a right click opens a menu of every action there once
+    /// released, unless the mouse moved enough to make it a camera drag. The
+    /// menu closes when the cursor moves away from it.
+    fn update_menu(&mut self) {
+        if self.input.take_pressed(Action::MoveTo) {
+            let action = match self.menu.take() {
+                Some(menu) => menu.option_at(self.cursor).map(|option| option.action),
+                None => self.menu_options(self.cursor).first().map(|option| option.action),
+            };
+            if let Some(action) = action {
+                self.perform(action);
+            }
+        }
+        if self.input.take_pressed(Action::OpenMenu) {
+            self.menu_press = Some((self.cursor, 0.0));
+        }
+        if let Some((position, moved)) = self.menu_press
+            && !self.input.is_held(Action::OpenMenu)
+        {
+            self.menu_press = None;
+            if moved < MENU_DRAG_THRESHOLD {
+                let menu = ContextMenu::new(position, self.menu_options(position));
+                let labels: Vec<&str> = menu.options().iter().map(|option| option.label.as_str()).collect();
+                log::info!("menu: {}", labels.join(", "));
+                self.menu = Some(menu);
+            }
+        }
+        if self.menu.as_ref().is_some_and(|menu| !menu.is_near(self.cursor)) {
+            self.menu = None;
+        }
+    }
+
+    /// Turns the camera with the right stick and zooms with the triggers. The
+    /// left stick walks relative to the camera or, in virtual cursor mode,
+    /// moves the cursor.
+    fn apply_gamepad(&mut self, axes: GamepadAxes, dt: f32) {
+        let gamepad = &self.settings.gamepad;
+        let mut look = axes.right_stick * gamepad.look_sensitivity * dt;
+        if !gamepad.invert_look {
+            look.y = -look.y;
+        }
+        let zoom = (axes.left_trigger - axes.right_trigger) * gamepad.zoom_speed * dt;
+        self.camera_controller.process_gamepad(look, zoom);
+
+        if axes.left_stick == Vec2::ZERO {
+            return;
+        }
+        if self.virtual_cursor {
+            let delta = axes.left_stick * gamepad.cursor_speed * dt;
+            self.cursor.x = (self.cursor.x + delta.x as f64).clamp(0.0, self.size.width as f64);
+            self.cursor.y = (self.cursor.y - delta.y as f64).clamp(0.0, self.size.height as f64);
+            self.cursor_warp = Some(self.cursor);
+        } else {
+            let yaw = self.camera.yaw.to_radians();
+            let forward = -Vec3::new(yaw.sin(), 0.0, yaw.cos());
+            let right = Vec3::new(yaw.cos(), 0.0, -yaw.sin());
+            let next = self.player.position + (forward * axes.left_stick.y + right * axes.left_stick.x) * WALK_SPEED * dt;
+            self.player.target_position = None;
+            self.pending_interaction = None;
+            if self.world.is_walkable(next) {
+                self.player.position = next;
+            }
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn poll_hot_reload(&mut self) {
+        let Some(hot_reloader) = self.hot_reloader.as_mut() else {
+            return;
+        };
+        for path in hot_reloader.poll() {
+            if hot_reload::is_shader(&path) {
+                self.reload_shader(&path);
+            } else if path.file_name() == Some(std::ffi::OsStr::new(SETTINGS_FILE)) {
+                self.reload_settings();
+            } else if self.assets.reload(&path) {
+                log::info!("reloading {}", path.display());
+            }
+        }
+    }
+
+    /// Rebuilds the pipelines from the shader on disk. Compile and validation
+    /// errors are logged and the current pipelines are kept.
+    #[cfg(debug_assertions)]
+    fn reload_shader(&mut self, path: &std::path::Path) {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                log::error!("failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipelines = MaterialPipelines::new(
+            &self.device,
+            &self.pipeline_layout,
+            &shader,
+            msaa_samples(&self.settings.graphics),
+        );
+        match pollster::block_on(self.device.pop_error_scope()) {
+            Some(e) => log::error!("shader reload failed, keeping the previous pipelines: {}", e),
+            None => {
+                self.pipelines = pipelines;
+                self.shader = shader;
+                log::info!("reloaded {}", path.display());
+            }
+        }
+    }
+
+    /// Re-reads the settings file and applies any changes.
+    fn reload_settings(&mut self) {
+        let path = self.assets.resolve(SETTINGS_FILE);
+        let settings = Settings::load(&path);
+        if settings.bindings != self.settings.bindings {
+            self.input.set_bindings(settings.bindings.clone());
+            self.settings.bindings = settings.bindings;
+            log::info!("applied key bindings from {}", path.display());
+        }
+        self.settings.camera = settings.camera;
+        self.settings.gamepad = settings.gamepad;
+        if settings.graphics != self.settings.graphics {
+            match self.apply_graphics_settings(settings.graphics) {
+                Ok(()) => log::info!("applied graphics settings from {}", path.display()),
+                Err(e) => log::error!("failed to apply graphics settings: {:#}", e),
+            }
+        }
+    }
+
+    /// Writes the current settings, including rebound keys, to the settings
+    /// file.
+    fn save_settings(&mut self) {
+        self.settings.bindings = self.input.bindings().clone();
+        let path = self.assets.resolve(SETTINGS_FILE);
+        match self.settings.save(&path) {
+            Ok(()) => log::info!("saved settings to {}", path.display()),
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+
+    fn player_transform(&self) -> Mat4 {
+        Mat4::from_translation(self.player.position) * character_model_transform()
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -343,93 +981,124 @@ impl State {
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let view_proj = self.projection.build_projection_matrix() * self.camera.build_view_matrix();
+        let frustum = Frustum::from_view_proj(&view_proj);
+        let mut stats = CullStats::default();
+        self.sky.update(&self.queue, &view_proj, &self.settings.graphics);
+        self.water.update(&self.queue, &view_proj, Vec3::from(self.settings.graphics.fog_color));
+
+        self.instances.begin_frame();
+        self.frame.terrain_instances = self.instances.push([Mat4::IDENTITY]);
+        self.frame.terrain_chunks = self.terrain.visible_chunks(&frustum, &mut stats);
+        // The player would fill the view from inside in first person.
+        let player = (self.camera.mode() != CameraMode::FirstPerson)
+            .then(|| (self.player_model, self.player_transform(), self.player_lod.level));
+        let entities: Vec<_> = player
+            .into_iter()
+            .chain(self.entities.iter().map(|entity| (entity.model, entity.transform(), entity.lod.level)))
+            .collect();
+        self.frame.batches = instances::batch_entities(&entities, &self.assets, &frustum, &mut stats, &mut self.instances);
+        self.instances.upload(&self.device, &self.queue);
+        self.build_overlay();
+        if stats != self.cull_stats {
+            log::debug!("drawing {} meshes, culled {}", stats.drawn, stats.culled);
+            self.cull_stats = stats;
+        }
+
         let mut encoder =
             self.device
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("Render Encoder"),
                 });
+        self.graph.execute(&mut encoder, &view, self);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+
+    fn build_overlay(&mut self) {
+        self.overlay.begin();
+        let graphics = &self.settings.graphics;
+        if graphics.show_grid {
+            self.overlay.add_grid(&self.world, self.camera.eye_position(), GRID_RADIUS, GRID_COLOR);
+        }
+        if graphics.show_path {
+            for (x, z) in self.player.planned_tiles() {
+                self.overlay.add_tile(&self.world, x, z, PATH_COLOR);
+            }
+        }
+        if graphics.tile_highlights {
+            let (x, z) = world::tile_at(self.player.position);
+            self.overlay.add_tile_outline(&self.world, x, z, PLAYER_TILE_COLOR);
+            if let Some(target) = self.player.target_position {
+                let (x, z) = world::tile_at(target);
+                self.overlay.add_tile_outline(&self.world, x, z, DESTINATION_TILE_COLOR);
+            }
+        }
+        if (graphics.show_grid || graphics.tile_highlights)
+            && let Some((x, z)) = self.hovered_tile
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+            self.overlay.add_tile_outline(&self.world, x, z, HOVERED_TILE_COLOR);
+        }
+        if let Some(marker) = &self.click_marker
+            && !marker.draw(&mut self.overlay, &self.world)
+        {
+            self.click_marker = None;
+        }
+        self.overlay.upload(&self.device, &self.queue);
+    }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+    fn draw_scene(&self, render_pass: &mut wgpu::RenderPass<'_>, pass: AlphaPass) {
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instances.slice());
 
-            let landscape_matrix = Mat4::IDENTITY;
-            let landscape_instance_data = InstanceRaw {
-                model: landscape_matrix.to_cols_array_2d(),
-            };
-            let landscape_instance_buffer =
-                self.device
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("Landscape Instance Buffer"),
-                        contents: bytemuck::cast_slice(&[landscape_instance_data]),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-            render_pass.set_vertex_buffer(1, landscape_instance_buffer.slice(..));
-            
-            for mesh in &self.landscape_model.meshes {
-                let material = &self.landscape_model.materials[mesh.material_index];
-                render_pass.set_bind_group(1, &material.bind_group, &[]);
-                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        let frame = &self.frame;
+        self.terrain.draw(render_pass, &frame.terrain_chunks, frame.terrain_instances.clone(), &self.assets, &self.pipelines, pass);
+        for batch in &frame.batches {
+            if let Some(model) = self.assets.model(batch.model) {
+                render_pass.draw_model(model, &batch.meshes, batch.instances.clone(), &self.assets, &self.pipelines, pass);
             }
+        }
+    }
+}
 
-            let scale = Mat4::from_scale(Vec3::splat(0.01));
-            let translation = Mat4::from_translation(self.player.position);
-            let rotation = Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2);
-            let player_model_matrix = translation * rotation * scale;
-            
-            let player_instance_data = InstanceRaw {
-                model: player_model_matrix.to_cols_array_2d(),
-            };
-            
-            self.queue.write_buffer(
-                &self.player_instance_buffer,
-                0,
-                bytemuck::cast_slice(&[player_instance_data]),
-            );
+impl Drop for State {
+    /// Entities and the player give back their model references, freeing
+    /// the models and the materials only they used.
+    fn drop(&mut self) {
+        for entity in self.entities.drain(..) {
+            self.assets.release_model(entity.model);
+        }
+        self.assets.release_model(self.player_model);
+    }
+}
 
-            render_pass.set_vertex_buffer(1, self.player_instance_buffer.slice(..));
+/// Stands the character model upright at the world's scale.
+fn character_model_transform() -> Mat4 {
+    Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2) * Mat4::from_scale(Vec3::splat(0.01))
+}
 
-            for mesh in &self.player_model.meshes {
-                if !self.player_model.materials.is_empty() {
-                    let material = &self.player_model.materials[mesh.material_index];
-                    render_pass.set_bind_group(1, &material.bind_group, &[]);
-                }
-                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
-            }
-        }
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-        Ok(())
+/// A trunk and a canopy built from boxes, standing in for a tree model until
+/// the game has one.
+fn tree_model(assets: &mut AssetManager) -> Handle<Model> {
+    let mut material = |name: &str, base_color_factor: [f32; 4]| {
+        let params = MaterialParams { base_color_factor, metallic_factor: 0.0, ..Default::default() };
+        assets.add_material(name, name, params, MaterialSlots::default())
+    };
+    let materials = vec![material("tree_bark", [0.3, 0.2, 0.1, 1.0]), material("tree_leaves", [0.15, 0.4, 0.1, 1.0])];
+
+    let trunk = MeshData::placeholder_box(0.15, 1.2);
+    let mut canopy = MeshData::placeholder_box(0.7, 1.4);
+    for vertex in &mut canopy.vertices {
+        vertex.position[1] += 1.0;
     }
-}
\ No newline at end of file
+    canopy.material_index = 1;
+    let meshes = [trunk, canopy].iter().map(|mesh| mesh.upload(assets.device())).collect();
+    assets.add_model("tree", Model::new(meshes, materials))
+}
+
+/// Sample count for the scene. Only 4x is guaranteed for its formats.
+fn msaa_samples(graphics: &GraphicsSettings) -> u32 {
+    if graphics.msaa_samples > 1 { 4 } else { 1 }
+}
@@ -0,0 +1,265 @@
+use crate::camera::CameraMode;
+use crate::input::Bindings;
+use anyhow::{Context, Result};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+
+/// File in the asset directory the settings are read from.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// User settings. Missing fields keep their defaults, so the file only needs
+/// the values that differ.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub graphics: GraphicsSettings,
+    pub camera: CameraSettings,
+    pub gamepad: GamepadSettings,
+    pub bindings: Bindings,
+}
+
+impl Settings {
+    /// Reads the settings at `path`, falling back to the defaults if the file
+    /// is missing or invalid.
+    pub fn load(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::read(path)
+            .inspect_err(|e| log::warn!("using default settings: {:#}", e))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self).context("failed to serialize settings")?;
+        std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut settings: Self = toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        settings.bindings.fill_defaults();
+        Ok(settings)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// Samples per pixel for the scene; 1 turns MSAA off and anything higher
+    /// uses 4x, the only count every GPU supports for the scene's formats.
+    pub msaa_samples: u32,
+    pub fxaa: bool,
+    /// ACES filmic tone mapping; without it HDR colours are clamped.
+    pub tone_mapping: bool,
+    /// Scales the scene's HDR colour before tone mapping.
+    pub exposure: f32,
+    pub bloom: bool,
+    /// Luminance above which pixels start to glow.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub color_grading: bool,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Multiplies the graded colour, e.g. to warm or cool the image.
+    pub tint: [f32; 3],
+    /// Colour of the sky overhead.
+    pub sky_color: [f32; 3],
+    /// Colour distant geometry fades to, which is also the sky at the horizon.
+    pub fog_color: [f32; 3],
+    /// How quickly the fog thickens with distance; 0 leaves only the fade at
+    /// the far plane.
+    pub fog_density: f32,
+    /// Highlights the tiles the player is about to walk over, for debugging.
+    pub show_path: bool,
+    /// Draws the tile grid over the terrain near the camera.
+    pub show_grid: bool,
+    /// Outlines the player's tile, the tile under the cursor and the tile
+    /// being walked to.
+    pub tile_highlights: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            msaa_samples: 1,
+            fxaa: true,
+            tone_mapping: true,
+            exposure: 1.0,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.3,
+            color_grading: true,
+            contrast: 1.05,
+            saturation: 1.1,
+            tint: [1.0, 1.0, 1.0],
+            sky_color: [0.15, 0.35, 0.75],
+            fog_color: [0.55, 0.65, 0.75],
+            fog_density: 0.012,
+            show_path: false,
+            show_grid: false,
+            tile_highlights: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GamepadSettings {
+    /// Stick deflection, from 0 to 1, below which a stick counts as centred.
+    pub stick_deadzone: f32,
+    pub trigger_deadzone: f32,
+    /// Camera turn rate at full right stick deflection, in degrees per second.
+    pub look_sensitivity: f32,
+    /// Makes pushing the right stick up look up instead of down.
+    pub invert_look: bool,
+    /// Zoom rate at a fully pulled trigger, in world units per second.
+    pub zoom_speed: f32,
+    /// Virtual cursor speed at full left stick deflection, in pixels per
+    /// second.
+    pub cursor_speed: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            stick_deadzone: 0.15,
+            trigger_deadzone: 0.05,
+            look_sensitivity: 120.0,
+            invert_look: false,
+            zoom_speed: 15.0,
+            cursor_speed: 800.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    #[serde(deserialize_with = "orbit_over_defaults")]
+    pub orbit: CameraModeSettings,
+    #[serde(deserialize_with = "first_person_over_defaults")]
+    pub first_person: CameraModeSettings,
+    #[serde(deserialize_with = "free_fly_over_defaults")]
+    pub free_fly: CameraModeSettings,
+    /// Free-fly movement speed, in world units per second.
+    pub fly_speed: f32,
+    /// Multiplies the fly speed while the fast or slow modifier is held.
+    pub fly_fast_multiplier: f32,
+    pub fly_slow_multiplier: f32,
+    /// Turns the orbit camera while the cursor rests near an edge of the
+    /// window.
+    pub edge_rotate: bool,
+    /// Width of the edge band, in pixels.
+    pub edge_margin: f32,
+    /// Turn rate with the cursor at the very edge, in degrees per second.
+    pub edge_speed: f32,
+}
+
+impl CameraSettings {
+    pub fn mode(&self, mode: CameraMode) -> &CameraModeSettings {
+        match mode {
+            CameraMode::Orbit => &self.orbit,
+            CameraMode::FirstPerson => &self.first_person,
+            CameraMode::FreeFly => &self.free_fly,
+        }
+    }
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            orbit: CameraModeSettings::ORBIT,
+            first_person: CameraModeSettings::FIRST_PERSON,
+            free_fly: CameraModeSettings::FREE_FLY,
+            fly_speed: 10.0,
+            fly_fast_multiplier: 4.0,
+            fly_slow_multiplier: 0.25,
+            edge_rotate: true,
+            edge_margin: 8.0,
+            edge_speed: 90.0,
+        }
+    }
+}
+
+/// Look and zoom limits of one camera mode. Angles are in degrees; pitch is
+/// positive looking down.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CameraModeSettings {
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Distance zoomed per wheel step.
+    pub zoom_step: f32,
+    /// Degrees turned per pixel of mouse movement.
+    pub mouse_sensitivity: f32,
+    /// Top turn rate with a rotate key held, in degrees per second.
+    pub rotation_speed: f32,
+    /// How quickly held keys reach the top turn rate, in degrees per second
+    /// squared.
+    pub rotation_acceleration: f32,
+    /// How quickly a turn slows once its keys are released or its drag
+    /// ends, per second. Higher stops sooner.
+    pub rotation_damping: f32,
+}
+
+impl CameraModeSettings {
+    pub const ORBIT: Self = Self {
+        min_pitch: 5.0,
+        max_pitch: 89.0,
+        min_distance: 2.0,
+        max_distance: 50.0,
+        zoom_step: 0.5,
+        mouse_sensitivity: 0.2,
+        rotation_speed: 120.0,
+        rotation_acceleration: 480.0,
+        rotation_damping: 6.0,
+    };
+    pub const FIRST_PERSON: Self = Self {
+        min_pitch: -85.0,
+        max_pitch: 85.0,
+        min_distance: 0.0,
+        max_distance: 0.0,
+        zoom_step: 0.5,
+        mouse_sensitivity: 0.15,
+        rotation_speed: 120.0,
+        rotation_acceleration: 480.0,
+        rotation_damping: 6.0,
+    };
+    pub const FREE_FLY: Self = Self {
+        min_pitch: -89.0,
+        max_pitch: 89.0,
+        min_distance: 0.0,
+        max_distance: 0.0,
+        zoom_step: 0.5,
+        mouse_sensitivity: 0.15,
+        rotation_speed: 120.0,
+        rotation_acceleration: 480.0,
+        rotation_damping: 6.0,
+    };
+}
+
+// Each mode has its own defaults, so a partial table in the file is laid over
+// those rather than over one default shared by every mode.
+fn orbit_over_defaults<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CameraModeSettings, D::Error> {
+    over_defaults(deserializer, CameraModeSettings::ORBIT)
+}
+
+fn first_person_over_defaults<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CameraModeSettings, D::Error> {
+    over_defaults(deserializer, CameraModeSettings::FIRST_PERSON)
+}
+
+fn free_fly_over_defaults<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CameraModeSettings, D::Error> {
+    over_defaults(deserializer, CameraModeSettings::FREE_FLY)
+}
+
+fn over_defaults<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    defaults: CameraModeSettings,
+) -> Result<CameraModeSettings, D::Error> {
+    let mut table = toml::Table::try_from(defaults).map_err(D::Error::custom)?;
+    table.extend(toml::Table::deserialize(deserializer)?);
+    table.try_into().map_err(D::Error::custom)
+}
@@ -1,12 +1,53 @@
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_end: f32,
 }
 
+struct MaterialUniform {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    alpha_mode: u32,
+}
+
+const ALPHA_MODE_OPAQUE: u32 = 0u;
+const ALPHA_MODE_MASK: u32 = 1u;
+
+const PI: f32 = 3.14159265;
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.4, 0.8, 0.3);
+const LIGHT_COLOR: vec3<f32> = vec3<f32>(3.0, 2.9, 2.7);
+const AMBIENT_COLOR: vec3<f32> = vec3<f32>(0.2, 0.22, 0.25);
+
 @group(1) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var<uniform> material: MaterialUniform;
 @group(1) @binding(1)
-var s_diffuse: sampler;
+var t_base_color: texture_2d<f32>;
+@group(1) @binding(2)
+var s_base_color: sampler;
+@group(1) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(4)
+var s_metallic_roughness: sampler;
+@group(1) @binding(5)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(6)
+var s_normal: sampler;
+@group(1) @binding(7)
+var t_occlusion: texture_2d<f32>;
+@group(1) @binding(8)
+var s_occlusion: sampler;
+@group(1) @binding(9)
+var t_emissive: texture_2d<f32>;
+@group(1) @binding(10)
+var s_emissive: sampler;
 
 
 @group(0) @binding(0)
@@ -17,6 +58,7 @@ struct VertexInput {
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
     @location(3) color: vec4<f32>,
+    @location(4) tangent: vec4<f32>,
 };
 
 struct InstanceInput {
@@ -31,6 +73,8 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
 };
 
 @vertex
@@ -47,15 +91,91 @@ fn vs_main(
 
     var out: VertexOutput;
 
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
     out.tex_coords = model.tex_coords;
     out.normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
     out.color = model.color;
     return out;
 }
 
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Squared exponential fog that also reaches full strength at `fog_end`.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_position.xyz);
+    let density = distance * camera.fog_density;
+    let fog = max(1.0 - exp(-density * density), smoothstep(camera.fog_end * 0.8, camera.fog_end, distance));
+    return mix(color, camera.fog_color, fog);
+}
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let texture_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    return texture_color * in.color;
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_sample = textureSample(t_base_color, s_base_color, in.tex_coords);
+    let mr_sample = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let normal_sample = textureSample(t_normal, s_normal, in.tex_coords);
+    let occlusion_sample = textureSample(t_occlusion, s_occlusion, in.tex_coords);
+    let emissive_sample = textureSample(t_emissive, s_emissive, in.tex_coords);
+
+    let base_color = base_sample * material.base_color_factor * in.color;
+    var alpha = base_color.a;
+    if material.alpha_mode == ALPHA_MODE_MASK {
+        if alpha < material.alpha_cutoff {
+            discard;
+        }
+        alpha = 1.0;
+    } else if material.alpha_mode == ALPHA_MODE_OPAQUE {
+        alpha = 1.0;
+    }
+
+    let metallic = material.metallic_factor * mr_sample.b;
+    let roughness = clamp(material.roughness_factor * mr_sample.g, 0.04, 1.0);
+    let occlusion = 1.0 + material.occlusion_strength * (occlusion_sample.r - 1.0);
+    let emissive = emissive_sample.rgb * material.emissive_factor;
+
+    // Back faces of double-sided materials are lit from their own side.
+    let face_sign = select(-1.0, 1.0, front_facing);
+    let geometric_normal = normalize(in.normal) * face_sign;
+    let tangent = normalize(in.tangent.xyz - geometric_normal * dot(geometric_normal, in.tangent.xyz));
+    let bitangent = cross(geometric_normal, tangent) * in.tangent.w;
+    var tangent_normal = normal_sample.xyz * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+    let n = normalize(mat3x3<f32>(tangent, bitangent, geometric_normal) * tangent_normal);
+
+    let v = normalize(camera.view_position.xyz - in.world_position);
+    let l = normalize(LIGHT_DIRECTION);
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * f
+        / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+    let diffuse = (1.0 - f) * (1.0 - metallic) * base_color.rgb / PI;
+
+    let direct = (diffuse + specular) * LIGHT_COLOR * n_dot_l;
+    let ambient = AMBIENT_COLOR * base_color.rgb * occlusion;
+
+    return vec4<f32>(apply_fog(direct + ambient + emissive, in.world_position), alpha);
+}
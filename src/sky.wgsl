@@ -0,0 +1,40 @@
+struct SkyUniform {
+    inv_view_proj: mat4x4<f32>,
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    sun_direction: vec4<f32>,
+}
+
+const SUN_COLOR: vec3<f32> = vec3<f32>(3.0, 2.9, 2.7);
+
+@group(0) @binding(0)
+var<uniform> sky: SkyUniform;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.position = vec4<f32>(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let near = sky.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - near.xyz / near.w);
+
+    // The horizon matches the fog, so fogged geometry fades into the sky.
+    var color = mix(sky.horizon_color.rgb, sky.zenith_color.rgb, sqrt(clamp(direction.y, 0.0, 1.0)));
+    color = mix(color, sky.horizon_color.rgb * 0.7, clamp(-direction.y * 4.0, 0.0, 1.0));
+
+    let sun = max(dot(direction, normalize(sky.sun_direction.xyz)), 0.0);
+    color += SUN_COLOR * (pow(sun, 2000.0) * 8.0 + pow(sun, 16.0) * 0.15);
+    return vec4<f32>(color, 1.0);
+}
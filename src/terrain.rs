@@ -0,0 +1,328 @@
+use crate::assets::{AssetManager, Handle, MaterialSlots, TextureSlot};
+use crate::culling::{Aabb, CullStats, Frustum};
+use crate::lod::{self, Lod};
+use crate::model::{AlphaPass, Material, MaterialParams, Vertex};
+use crate::renderer::MaterialPipelines;
+use crate::world::{World, CHUNK_SIZE, WORLD_SIZE};
+use glam::Vec3;
+use std::collections::HashMap;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+/// Tile spacing of the chunk grid at each detail level.
+const LOD_STEPS: [usize; 3] = [1, 2, 4];
+/// Screen sizes below which a chunk drops to the next coarser level.
+const LOD_THRESHOLDS: [f32; 2] = [0.6, 0.3];
+/// Vertices along each side of a chunk.
+const GRID: usize = CHUNK_SIZE + 1;
+
+struct Chunk {
+    vertex_buffer: wgpu::Buffer,
+    bounds: Aabb,
+    lod: Lod,
+}
+
+struct ChunkIndices {
+    buffer: wgpu::Buffer,
+    count: u32,
+}
+
+/// Edges of a chunk, in the order used for neighbour levels.
+#[derive(Clone, Copy)]
+enum Edge {
+    MinX,
+    MaxX,
+    MinZ,
+    MaxZ,
+}
+
+impl Edge {
+    const ALL: [Edge; 4] = [Edge::MinX, Edge::MaxX, Edge::MinZ, Edge::MaxZ];
+
+    /// Grid position `depth` tiles in from this edge, `along` tiles along it.
+    fn point(self, along: usize, depth: usize) -> (usize, usize) {
+        match self {
+            Edge::MinX => (depth, along),
+            Edge::MaxX => (CHUNK_SIZE - depth, along),
+            Edge::MinZ => (along, depth),
+            Edge::MaxZ => (along, CHUNK_SIZE - depth),
+        }
+    }
+}
+
+/// The ground, split into chunks that are culled and reduced in detail with
+/// distance independently. All chunks share one vertex layout, so the index
+/// buffers for every level and neighbour combination are shared too.
+pub struct Terrain {
+    chunks: Vec<Chunk>,
+    chunks_per_side: usize,
+    /// Keyed by a chunk's level and the level used along each of its edges,
+    /// which is the coarser of its own and the neighbour's so the shared
+    /// edge has the same vertices on both sides.
+    indices: HashMap<(usize, [usize; 4]), ChunkIndices>,
+    material: Handle<Material>,
+}
+
+impl Terrain {
+    pub fn new(assets: &mut AssetManager, world: &World) -> Self {
+        let device = assets.device().clone();
+        let chunks_per_side = (WORLD_SIZE - 1).div_ceil(CHUNK_SIZE);
+
+        let mut chunks = Vec::with_capacity(chunks_per_side * chunks_per_side);
+        for chunk_z in 0..chunks_per_side {
+            for chunk_x in 0..chunks_per_side {
+                // Chunks past the end of the world repeat its last row and
+                // column, which only adds zero-area triangles.
+                let mut vertices = Vec::with_capacity(GRID * GRID);
+                for z in 0..GRID {
+                    for x in 0..GRID {
+                        let world_x = (chunk_x * CHUNK_SIZE + x).min(WORLD_SIZE - 1);
+                        let world_z = (chunk_z * CHUNK_SIZE + z).min(WORLD_SIZE - 1);
+                        let normal = world.normal(world_x, world_z);
+                        // Along +X, which is where the texture's u runs.
+                        let tangent = Vec3::new(normal.y, -normal.x, 0.0).normalize();
+                        vertices.push(Vertex {
+                            position: [world_x as f32, world.heightmap[world_x][world_z], world_z as f32],
+                            tex_coords: [world_x as f32, world_z as f32],
+                            normal: normal.to_array(),
+                            color: [1.0, 1.0, 1.0, 1.0],
+                            tangent: tangent.extend(1.0).to_array(),
+                        });
+                    }
+                }
+
+                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Terrain Chunk {} {} Vertex Buffer", chunk_x, chunk_z)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                let bounds = Aabb::from_points(vertices.iter().map(|v| Vec3::from(v.position)));
+                chunks.push(Chunk { vertex_buffer, bounds, lod: Lod::default() });
+            }
+        }
+
+        let mut indices = HashMap::new();
+        for (level, &step) in LOD_STEPS.iter().enumerate() {
+            let coarser = level..LOD_STEPS.len();
+            for min_x in coarser.clone() {
+                for max_x in coarser.clone() {
+                    for min_z in coarser.clone() {
+                        for max_z in coarser.clone() {
+                            let edges = [min_x, max_x, min_z, max_z];
+                            let data = chunk_indices(step, edges.map(|e| LOD_STEPS[e]));
+                            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some(&format!("Terrain LOD {} {:?} Index Buffer", level, edges)),
+                                contents: bytemuck::cast_slice(&data),
+                                usage: wgpu::BufferUsages::INDEX,
+                            });
+                            indices.insert((level, edges), ChunkIndices { buffer, count: data.len() as u32 });
+                        }
+                    }
+                }
+            }
+        }
+
+        let material = match assets.acquire_material("stone") {
+            Some(handle) => handle,
+            None => {
+                // Loaded from the asset directory like any other texture, so
+                // edits to it are hot-reloaded.
+                let texture = assets.load_texture_async("stone.png");
+                // Texture coordinates are in tiles, so the texture repeats once
+                // per tile at every detail level.
+                let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: Some("Terrain Sampler"),
+                    address_mode_u: wgpu::AddressMode::Repeat,
+                    address_mode_v: wgpu::AddressMode::Repeat,
+                    min_filter: wgpu::FilterMode::Linear,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    mipmap_filter: wgpu::FilterMode::Linear,
+                    anisotropy_clamp: 8,
+                    ..Default::default()
+                });
+                let slots = MaterialSlots {
+                    base_color: Some(TextureSlot { texture, sampler: Some(sampler) }),
+                    ..Default::default()
+                };
+                let params = MaterialParams {
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                };
+                assets.add_material("stone", "stone", params, slots)
+            }
+        };
+
+        Self { chunks, chunks_per_side, indices, material }
+    }
+
+    /// Picks each chunk's detail level from its size on screen.
+    pub fn update_lod(&mut self, eye: Vec3, fovy: f32) {
+        for chunk in &mut self.chunks {
+            let size = lod::screen_size(&chunk.bounds, eye, fovy);
+            chunk.lod.update(size, &LOD_THRESHOLDS, LOD_STEPS.len() - 1);
+        }
+    }
+
+    /// Indices of the chunks inside the frustum.
+    pub fn visible_chunks(&self, frustum: &Frustum, stats: &mut CullStats) -> Vec<usize> {
+        (0..self.chunks.len())
+            .filter(|&i| {
+                let visible = frustum.intersects(&self.chunks[i].bounds);
+                stats.add(visible, 1);
+                visible
+            })
+            .collect()
+    }
+
+    /// Draws the given chunks with the instance buffer already bound at slot 1.
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        chunks: &[usize],
+        instances: Range<u32>,
+        assets: &AssetManager,
+        pipelines: &MaterialPipelines,
+        pass: AlphaPass,
+    ) {
+        let Some(material) = assets.material(self.material) else {
+            return;
+        };
+        if !pass.includes(material.params.alpha_mode) {
+            return;
+        }
+        render_pass.set_pipeline(pipelines.select(&material.params));
+        render_pass.set_bind_group(1, &material.bind_group, &[]);
+
+        for &i in chunks {
+            let level = self.chunks[i].lod.level;
+            let edges = Edge::ALL.map(|edge| self.neighbour(i, edge).map_or(level, |n| n.lod.level.max(level)));
+            let Some(indices) = self.indices.get(&(level, edges)) else {
+                continue;
+            };
+            render_pass.set_vertex_buffer(0, self.chunks[i].vertex_buffer.slice(..));
+            render_pass.set_index_buffer(indices.buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..indices.count, 0, instances.clone());
+        }
+    }
+
+    fn neighbour(&self, index: usize, edge: Edge) -> Option<&Chunk> {
+        let (x, z) = (index % self.chunks_per_side, index / self.chunks_per_side);
+        let (x, z) = match edge {
+            Edge::MinX => (x.checked_sub(1)?, z),
+            Edge::MaxX => (x + 1, z),
+            Edge::MinZ => (x, z.checked_sub(1)?),
+            Edge::MaxZ => (x, z + 1),
+        };
+        if x >= self.chunks_per_side || z >= self.chunks_per_side {
+            return None;
+        }
+        self.chunks.get(z * self.chunks_per_side + x)
+    }
+}
+
+/// Triangles for a chunk drawn with tiles `step` apart, whose edges only use
+/// vertices `edge_steps` apart. The interior is a regular grid; the outer ring
+/// of each edge zips the interior's border to the sparser edge vertices,
+/// which keeps the seam to a coarser neighbour free of cracks.
+fn chunk_indices(step: usize, edge_steps: [usize; 4]) -> Vec<u32> {
+    let mut indices = Vec::new();
+    let index = |(x, z): (usize, usize)| (z * GRID + x) as u32;
+    // Wind every triangle counter-clockwise seen from above, whichever way the
+    // strip runs.
+    let mut triangle = |a: (usize, usize), b: (usize, usize), c: (usize, usize)| {
+        let (ax, az) = (a.0 as i64, a.1 as i64);
+        let cross = (b.1 as i64 - az) * (c.0 as i64 - ax) - (b.0 as i64 - ax) * (c.1 as i64 - az);
+        if cross > 0 {
+            indices.extend([index(a), index(b), index(c)]);
+        } else if cross < 0 {
+            indices.extend([index(a), index(c), index(b)]);
+        }
+    };
+
+    for z in (step..CHUNK_SIZE - step).step_by(step) {
+        for x in (step..CHUNK_SIZE - step).step_by(step) {
+            let (top_left, top_right) = ((x, z), (x + step, z));
+            let (bottom_left, bottom_right) = ((x, z + step), (x + step, z + step));
+            triangle(top_left, bottom_left, top_right);
+            triangle(top_right, bottom_left, bottom_right);
+        }
+    }
+
+    for (edge, edge_step) in Edge::ALL.into_iter().zip(edge_steps) {
+        let outer: Vec<usize> = (0..=CHUNK_SIZE).step_by(edge_step).collect();
+        let inner: Vec<usize> = (step..=CHUNK_SIZE - step).step_by(step).collect();
+        let (mut a, mut b) = (0, 0);
+        while a + 1 < outer.len() || b + 1 < inner.len() {
+            let advance_outer = b + 1 == inner.len() || (a + 1 < outer.len() && outer[a + 1] <= inner[b + 1]);
+            if advance_outer {
+                triangle(edge.point(outer[a], 0), edge.point(outer[a + 1], 0), edge.point(inner[b], step));
+                a += 1;
+            } else {
+                triangle(edge.point(outer[a], 0), edge.point(inner[b + 1], step), edge.point(inner[b], step));
+                b += 1;
+            }
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangles(indices: &[u32]) -> impl Iterator<Item = [(i64, i64); 3]> + '_ {
+        let point = |i: u32| ((i as usize % GRID) as i64, (i as usize / GRID) as i64);
+        indices.chunks_exact(3).map(move |triangle| [point(triangle[0]), point(triangle[1]), point(triangle[2])])
+    }
+
+    /// Twice the signed area seen from above, positive for counter-clockwise
+    /// triangles as wound by `chunk_indices`.
+    fn doubled_area([a, b, c]: [(i64, i64); 3]) -> i64 {
+        (b.1 - a.1) * (c.0 - a.0) - (b.0 - a.0) * (c.1 - a.1)
+    }
+
+    /// Every level with every mix of equal or coarser edges, as the terrain
+    /// builds them.
+    fn edge_combinations() -> Vec<(usize, [usize; 4])> {
+        let mut combinations = Vec::new();
+        for step in LOD_STEPS {
+            let coarser: Vec<usize> = LOD_STEPS.into_iter().filter(|&s| s >= step).collect();
+            for i in 0..coarser.len().pow(4) {
+                let edges = [0, 1, 2, 3].map(|edge| coarser[i / coarser.len().pow(edge) % coarser.len()]);
+                combinations.push((step, edges));
+            }
+        }
+        combinations
+    }
+
+    #[test]
+    fn chunks_are_covered_without_gaps_or_overlaps() {
+        for (step, edges) in edge_combinations() {
+            let indices = chunk_indices(step, edges);
+            let mut total = 0;
+            for triangle in triangles(&indices) {
+                let area = doubled_area(triangle);
+                assert!(area > 0, "step {} edges {:?}: {:?} is not counter-clockwise", step, edges, triangle);
+                total += area;
+            }
+            assert_eq!(total, 2 * (CHUNK_SIZE * CHUNK_SIZE) as i64, "step {} edges {:?}", step, edges);
+        }
+    }
+
+    #[test]
+    fn edges_only_use_vertices_a_coarser_neighbour_has() {
+        for (step, edges) in edge_combinations() {
+            let indices = chunk_indices(step, edges);
+            let used: Vec<(i64, i64)> = triangles(&indices).flatten().collect();
+            for (edge, edge_step) in Edge::ALL.into_iter().zip(edges) {
+                let on_edge: std::collections::BTreeSet<usize> = used
+                    .iter()
+                    .map(|&(x, z)| (x as usize, z as usize))
+                    .filter_map(|point| (0..=CHUNK_SIZE).find(|&along| edge.point(along, 0) == point))
+                    .collect();
+                let expected = (0..=CHUNK_SIZE).step_by(edge_step).collect();
+                assert_eq!(on_edge, expected, "step {} edges {:?}", step, edges);
+            }
+        }
+    }
+}
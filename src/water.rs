@@ -0,0 +1,224 @@
+use crate::post_process::HDR_FORMAT;
+use crate::render_graph::RenderGraph;
+use crate::renderer::DEPTH_ATTACHMENT;
+use crate::world::{World, WORLD_SIZE};
+use glam::{Mat4, Vec3};
+use std::time::Instant;
+use wgpu::util::DeviceExt;
+
+const SHALLOW_COLOR: [f32; 4] = [0.1, 0.45, 0.45, 1.0];
+const DEEP_COLOR: [f32; 4] = [0.02, 0.1, 0.2, 1.0];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    shallow_color: [f32; 4],
+    deep_color: [f32; 4],
+    sky_color: [f32; 4],
+    time: f32,
+    level: f32,
+    _padding: [f32; 2],
+}
+
+/// Layout entry for reading the scene's depth at `binding` in a pass that
+/// binds it as a read-only depth attachment. `sample_count` is that of the
+/// scene's depth buffer.
+fn scene_depth_entry(binding: u32, sample_count: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: sample_count > 1,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Depth,
+        },
+        count: None,
+    }
+}
+
+/// Prefixes `source` with `t_scene_depth` at group 1 and `binding`, and a
+/// `scene_depth(coord)` function reading it. WGSL has separate types for
+/// multisampled textures, so the binding is declared here to match the scene.
+fn with_scene_depth(source: &str, binding: u32, sample_count: u32) -> String {
+    let depth_type = if sample_count > 1 { "texture_depth_multisampled_2d" } else { "texture_depth_2d" };
+    format!(
+        "@group(1) @binding({})\nvar t_scene_depth: {};\n\
+         fn scene_depth(coord: vec2<i32>) -> f32 {{ return textureLoad(t_scene_depth, coord, 0); }}\n{}",
+        binding, depth_type, source
+    )
+}
+
+/// The surface over every water tile of the world, drawn as a blended pass
+/// after the scene. It reads the scene's depth to tint, fade and foam by how
+/// much water lies in front of the ground, and is depth tested against it
+/// without writing.
+pub struct Water {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    level: f32,
+    started: Instant,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Water {
+    /// `sample_count` is that of the scene's depth buffer.
+    pub fn new(
+        device: &wgpu::Device,
+        world: &World,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let mut vertices: Vec<[f32; 3]> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        for z in 0..WORLD_SIZE - 1 {
+            for x in 0..WORLD_SIZE - 1 {
+                if !world.is_water(x, z) {
+                    continue;
+                }
+                let first = vertices.len() as u32;
+                let (x, z, y) = (x as f32, z as f32, world.water_level);
+                vertices.extend([[x, y, z], [x + 1.0, y, z], [x, y, z + 1.0], [x + 1.0, y, z + 1.0]]);
+                indices.extend([first, first + 2, first + 1, first + 1, first + 2, first + 3]);
+            }
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Water Uniform Buffer"),
+            size: std::mem::size_of::<WaterUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("water_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                scene_depth_entry(1, sample_count),
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Water Shader"),
+            source: wgpu::ShaderSource::Wgsl(with_scene_depth(include_str!("water.wgsl"), 1, sample_count).into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Water Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                }],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, ..Default::default() },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            level: world.water_level,
+            started: Instant::now(),
+            pipeline,
+            layout,
+            uniform_buffer,
+            bind_group: None,
+        }
+    }
+
+    /// Binds the scene depth from `graph`; needed again whenever the graph
+    /// recreates its attachments.
+    pub fn bind<C>(&mut self, device: &wgpu::Device, graph: &RenderGraph<C>) {
+        self.bind_group = graph.view(DEPTH_ATTACHMENT).map(|depth| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("water_bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: self.uniform_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(depth) },
+                ],
+            })
+        });
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, view_proj: &Mat4, sky_color: Vec3) {
+        let uniform = WaterUniform {
+            inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+            shallow_color: SHALLOW_COLOR,
+            deep_color: DEEP_COLOR,
+            sky_color: sky_color.extend(1.0).to_array(),
+            time: self.started.elapsed().as_secs_f32(),
+            level: self.level,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        let Some(bind_group) = &self.bind_group else {
+            return;
+        };
+        if self.num_indices == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
@@ -0,0 +1,102 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_end: f32,
+}
+
+struct WaterUniform {
+    inv_view_proj: mat4x4<f32>,
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    sky_color: vec4<f32>,
+    time: f32,
+    level: f32,
+}
+
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.4, 0.8, 0.3);
+const LIGHT_COLOR: vec3<f32> = vec3<f32>(3.0, 2.9, 2.7);
+// How quickly colour and opacity approach their deep-water values, per unit
+// of water depth and of view distance through the water.
+const DEPTH_FALLOFF: f32 = 1.2;
+const OPACITY_FALLOFF: f32 = 0.8;
+// Water shallower than this foams.
+const FOAM_DEPTH: f32 = 0.25;
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> water: WaterUniform;
+// `t_scene_depth` is declared by the renderer at binding 1 of group 1, as a
+// multisampled or single-sampled depth texture to match the scene.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    return out;
+}
+
+// Normal of a sum of sine waves travelling in different directions.
+fn wave_normal(position: vec2<f32>, time: f32) -> vec3<f32> {
+    let directions = array<vec2<f32>, 4>(
+        vec2<f32>(0.8, 0.6),
+        vec2<f32>(-0.5, 0.87),
+        vec2<f32>(0.2, -0.98),
+        vec2<f32>(-0.94, -0.34),
+    );
+    let frequencies = array<f32, 4>(1.3, 2.1, 3.7, 5.3);
+    let amplitudes = array<f32, 4>(0.05, 0.03, 0.015, 0.008);
+    let speeds = array<f32, 4>(1.1, 1.5, 2.3, 3.1);
+
+    var slope = vec2<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        let phase = dot(directions[i], position) * frequencies[i] + time * speeds[i];
+        slope += directions[i] * frequencies[i] * amplitudes[i] * cos(phase);
+    }
+    return normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+}
+
+// Squared exponential fog that also reaches full strength at `fog_end`.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_position.xyz);
+    let density = distance * camera.fog_density;
+    let fog = max(1.0 - exp(-density * density), smoothstep(camera.fog_end * 0.8, camera.fog_end, distance));
+    return mix(color, camera.fog_color, fog);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The ground under the water, from the depth the pipeline tests against.
+    let depth = scene_depth(vec2<i32>(in.clip_position.xy));
+
+    let size = vec2<f32>(textureDimensions(t_scene_depth));
+    let ndc = vec2<f32>(in.clip_position.x / size.x * 2.0 - 1.0, 1.0 - in.clip_position.y / size.y * 2.0);
+    let scene = water.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let scene_position = scene.xyz / scene.w;
+    let water_depth = max(water.level - scene_position.y, 0.0);
+    let view_depth = distance(in.world_position, scene_position);
+
+    let n = wave_normal(in.world_position.xz, water.time);
+    let v = normalize(camera.view_position.xyz - in.world_position);
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(n, v), 0.0), 5.0);
+
+    var color = mix(water.shallow_color.rgb, water.deep_color.rgb, 1.0 - exp(-water_depth * DEPTH_FALLOFF));
+    color = mix(color, water.sky_color.rgb, fresnel);
+    let h = normalize(v + normalize(LIGHT_DIRECTION));
+    color += LIGHT_COLOR * pow(max(dot(n, h), 0.0), 200.0);
+
+    let ripple = sin(in.world_position.x * 3.1 + water.time * 1.7) * sin(in.world_position.z * 2.7 - water.time * 1.3);
+    let foam = (1.0 - smoothstep(0.0, FOAM_DEPTH, water_depth)) * smoothstep(-0.2, 0.6, ripple);
+    color = mix(color, vec3<f32>(0.9, 0.95, 1.0), foam);
+
+    let alpha = clamp(1.0 - exp(-view_depth * OPACITY_FALLOFF) + fresnel, 0.0, 1.0);
+    return vec4<f32>(apply_fog(color, in.world_position), max(alpha, foam));
+}
@@ -1,21 +1,285 @@
+use glam::Vec3;
+
 pub const WORLD_SIZE: usize = 64;
+/// Tiles along each side of a terrain chunk, the unit of terrain culling.
+pub const CHUNK_SIZE: usize = 16;
+/// Height of the water surface. Tiles with ground below it are water.
+pub const WATER_LEVEL: f32 = -0.3;
+
+/// Where a ray meets the terrain.
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    /// The tile hit, by its lowest corner like [`World::is_water`].
+    pub tile: (usize, usize),
+    pub point: Vec3,
+}
 
 pub struct World {
     pub heightmap: Vec<Vec<f32>>,
+    pub water_level: f32,
+    /// Indexed like `heightmap`, one entry per tile between four heights.
+    water: Vec<Vec<bool>>,
 }
 
 impl World {
     pub fn new() -> Self {
 
-        let heightmap = vec![vec![0.0; WORLD_SIZE]; WORLD_SIZE];
+        let mut heightmap = vec![vec![0.0; WORLD_SIZE]; WORLD_SIZE];
+
+        // A lake towards one corner.
+        let (centre_x, centre_z, radius) = (46.0, 18.0, 9.0);
+        for (x, column) in heightmap.iter_mut().enumerate() {
+            for (z, height) in column.iter_mut().enumerate() {
+                let distance_sq = (x as f32 - centre_x).powi(2) + (z as f32 - centre_z).powi(2);
+                *height -= 1.5 * (1.0 - distance_sq / (radius * radius)).max(0.0);
+            }
+        }
+
+        Self::with_water_level(heightmap, WATER_LEVEL)
+    }
+
+    /// Flags every tile with a corner below `water_level` as water.
+    pub fn with_water_level(heightmap: Vec<Vec<f32>>, water_level: f32) -> Self {
+        let tiles = WORLD_SIZE - 1;
+        let water = (0..tiles)
+            .map(|x| {
+                (0..tiles)
+                    .map(|z| {
+                        let corners = [heightmap[x][z], heightmap[x + 1][z], heightmap[x][z + 1], heightmap[x + 1][z + 1]];
+                        corners.iter().any(|&height| height < water_level)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { heightmap, water_level, water }
+    }
+
+    /// Height of the terrain as drawn at `(x, z)`, on the two triangles each
+    /// tile is split into, so that things on the ground sit exactly on it.
+    pub fn surface_height(&self, x: f32, z: f32) -> f32 {
+        let limit = (WORLD_SIZE - 1) as f32;
+        let (x, z) = (x.clamp(0.0, limit), z.clamp(0.0, limit));
+        let (x0, z0) = tile_at(Vec3::new(x, 0.0, z));
+        let (fx, fz) = (x - x0 as f32, z - z0 as f32);
+        let height = |dx: usize, dz: usize| self.heightmap[x0 + dx][z0 + dz];
+        // The diagonal runs from (x + 1, z) to (x, z + 1).
+        if fx + fz <= 1.0 {
+            height(0, 0) + (height(1, 0) - height(0, 0)) * fx + (height(0, 1) - height(0, 0)) * fz
+        } else {
+            height(1, 1) + (height(0, 1) - height(1, 1)) * (1.0 - fx) + (height(1, 0) - height(1, 1)) * (1.0 - fz)
+        }
+    }
+
+    /// Normal of the ground at the height sample `(x, z)`, from the slope
+    /// between its neighbours, or to the one neighbour at the world's edge.
+    pub fn normal(&self, x: usize, z: usize) -> Vec3 {
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(WORLD_SIZE - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(WORLD_SIZE - 1));
+        let dx = (self.heightmap[x1][z] - self.heightmap[x0][z]) / (x1 - x0) as f32;
+        let dz = (self.heightmap[x][z1] - self.heightmap[x][z0]) / (z1 - z0) as f32;
+        Vec3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    /// Whether the tile with its lowest corner at `(x, z)` is water. Tiles
+    /// outside the world aren't.
+    pub fn is_water(&self, x: usize, z: usize) -> bool {
+        self.water.get(x).and_then(|column| column.get(z)).copied().unwrap_or(false)
+    }
+
+    /// Whether the player may stand at `position`: inside the world and not on
+    /// a water tile.
+    pub fn is_walkable(&self, position: Vec3) -> bool {
+        let limit = (WORLD_SIZE - 1) as f32;
+        if !(0.0..=limit).contains(&position.x) || !(0.0..=limit).contains(&position.z) {
+            return false;
+        }
+        let (x, z) = tile_at(position);
+        !self.is_water(x, z)
+    }
+
+    /// First point where the ray from `origin` meets the terrain within
+    /// `max_distance`. Walks the tiles under the ray in order and tests the
+    /// same two triangles per tile the terrain mesh draws at full detail.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize();
+        let limit = (WORLD_SIZE - 1) as f32;
+
+        // Clip the ray to the world's extent on the ground plane.
+        let (mut t_enter, mut t_exit) = (0.0f32, max_distance);
+        for (start, step) in [(origin.x, direction.x), (origin.z, direction.z)] {
+            if step == 0.0 {
+                if !(0.0..=limit).contains(&start) {
+                    return None;
+                }
+            } else {
+                let (a, b) = (-start / step, (limit - start) / step);
+                t_enter = t_enter.max(a.min(b));
+                t_exit = t_exit.min(a.max(b));
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        let entry = origin + direction * t_enter;
+        let (mut x, mut z) = tile_at(entry);
+        // Distance along the ray to the next tile boundary on an axis, and
+        // between boundaries on it.
+        let boundary = |start: f32, step: f32, cell: usize| match step {
+            s if s > 0.0 => (cell as f32 + 1.0 - start) / s,
+            s if s < 0.0 => (cell as f32 - start) / s,
+            _ => f32::INFINITY,
+        };
+        let (mut next_x, mut next_z) = (boundary(origin.x, direction.x, x), boundary(origin.z, direction.z, z));
+        let (delta_x, delta_z) = (1.0 / direction.x.abs(), 1.0 / direction.z.abs());
+
+        loop {
+            if let Some(hit) = self.intersect_tile(x, z, origin, direction, t_exit) {
+                return Some(hit);
+            }
+            if next_x < next_z {
+                if next_x > t_exit {
+                    return None;
+                }
+                x = x.checked_add_signed(direction.x.signum() as isize).filter(|&x| x < WORLD_SIZE - 1)?;
+                next_x += delta_x;
+            } else {
+                if next_z > t_exit {
+                    return None;
+                }
+                z = z.checked_add_signed(direction.z.signum() as isize).filter(|&z| z < WORLD_SIZE - 1)?;
+                next_z += delta_z;
+            }
+        }
+    }
+
+    /// Nearest hit on either triangle of a tile. They share the diagonal from
+    /// `(x + 1, z)` to `(x, z + 1)`, as in the terrain mesh.
+    fn intersect_tile(&self, x: usize, z: usize, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let corner = |dx: usize, dz: usize| {
+            Vec3::new((x + dx) as f32, self.heightmap[x + dx][z + dz], (z + dz) as f32)
+        };
+        let (top_left, top_right) = (corner(0, 0), corner(1, 0));
+        let (bottom_left, bottom_right) = (corner(0, 1), corner(1, 1));
+        [[top_left, bottom_left, top_right], [top_right, bottom_left, bottom_right]]
+            .into_iter()
+            .filter_map(|triangle| intersect_triangle(origin, direction, triangle))
+            .filter(|&distance| distance <= max_distance)
+            .min_by(f32::total_cmp)
+            .map(|distance| RayHit { tile: (x, z), point: origin + direction * distance })
+    }
+}
+
+/// The tile under `position`, by its lowest corner. Positions outside the
+/// world give the nearest tile on its edge.
+pub fn tile_at(position: Vec3) -> (usize, usize) {
+    let tile = |coordinate: f32| (coordinate.max(0.0) as usize).min(WORLD_SIZE - 2);
+    (tile(position.x), tile(position.z))
+}
+
+/// Distance along the ray to where it crosses the triangle, if it does in
+/// front of `origin` (Möller–Trumbore).
+fn intersect_triangle(origin: Vec3, direction: Vec3, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let (edge1, edge2) = (b - a, c - a);
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = origin - a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse;
+    (distance >= 0.0).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A world whose height at `(x, z)` is `height(x, z)`, with water below 0.
+    fn world_with(height: impl Fn(f32, f32) -> f32) -> World {
+        let heightmap = (0..WORLD_SIZE)
+            .map(|x| (0..WORLD_SIZE).map(|z| height(x as f32, z as f32)).collect())
+            .collect();
+        World::with_water_level(heightmap, 0.0)
+    }
+
+    #[test]
+    fn flat_ground_faces_up() {
+        let world = world_with(|_, _| 2.0);
+        assert_eq!(world.normal(10, 10), Vec3::Y);
+        assert_eq!(world.normal(0, WORLD_SIZE - 1), Vec3::Y);
+    }
 
-        Self { heightmap }
+    #[test]
+    fn normals_lean_away_from_the_slope_up_to_the_edges() {
+        let world = world_with(|x, _| x);
+        let expected = Vec3::new(-1.0, 1.0, 0.0).normalize();
+        for (x, z) in [(10, 10), (0, 5), (WORLD_SIZE - 1, 5)] {
+            assert!(world.normal(x, z).abs_diff_eq(expected, 1e-6), "at ({}, {})", x, z);
+        }
     }
 
-    pub fn get_height(&self, x: f32, z: f32) -> f32 {
-        let x_clamped = x.max(0.0).min((WORLD_SIZE - 1) as f32);
-        let z_clamped = z.max(0.0).min((WORLD_SIZE - 1) as f32);
+    fn assert_hit(hit: Option<RayHit>, tile: (usize, usize), point: Vec3) {
+        let hit = hit.expect("ray should hit the ground");
+        assert_eq!(hit.tile, tile);
+        assert!(hit.point.abs_diff_eq(point, 1e-4), "hit at {} instead of {}", hit.point, point);
+    }
+
+    #[test]
+    fn straight_down_rays_hit_the_tile_below() {
+        let world = world_with(|_, _| 2.0);
+        let hit = world.raycast(Vec3::new(10.5, 50.0, 20.25), Vec3::NEG_Y, 100.0);
+        assert_hit(hit, (10, 20), Vec3::new(10.5, 2.0, 20.25));
+        // On either side of the diagonal splitting a tile.
+        let world = world_with(|x, z| x + z);
+        assert_hit(world.raycast(Vec3::new(5.2, 50.0, 7.2), Vec3::NEG_Y, 100.0), (5, 7), Vec3::new(5.2, 12.4, 7.2));
+        assert_hit(world.raycast(Vec3::new(5.8, 50.0, 7.8), Vec3::NEG_Y, 100.0), (5, 7), Vec3::new(5.8, 13.6, 7.8));
+    }
 
-        self.heightmap[x_clamped.round() as usize][z_clamped.round() as usize]
+    #[test]
+    fn hits_beyond_max_distance_are_ignored() {
+        let world = world_with(|_, _| 2.0);
+        let origin = Vec3::new(10.5, 50.0, 20.5);
+        assert!(world.raycast(origin, Vec3::NEG_Y, 47.9).is_none());
+        assert_hit(world.raycast(origin, Vec3::NEG_Y, 48.0), (10, 20), Vec3::new(10.5, 2.0, 20.5));
+        // Along a slanted ray too, where the cutoff falls tiles before the hit.
+        let direction = Vec3::new(1.0, -1.0, 0.0);
+        assert!(world.raycast(Vec3::new(5.5, 12.0, 20.5), direction, 10.0 * 2f32.sqrt() - 0.1).is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grazing_rays_find_the_first_rise() {
+        // Flat ground with a ridge one unit high along x = 30.
+        let world = world_with(|x, _| if x == 30.0 { 1.0 } else { 0.0 });
+        let hit = world.raycast(Vec3::new(5.0, 0.5, 10.5), Vec3::new(1.0, -0.001, 0.0), 100.0);
+        let hit = hit.expect("ray should hit the ridge");
+        assert_eq!(hit.tile, (29, 10));
+        assert!((hit.point.x - 29.48).abs() < 0.01, "hit at {}", hit.point);
+        // Level rays above flat ground never come down to it.
+        let world = world_with(|_, _| 0.0);
+        assert!(world.raycast(Vec3::new(5.0, 0.5, 10.5), Vec3::X, 100.0).is_none());
+    }
+
+    #[test]
+    fn rays_from_outside_the_world_enter_it() {
+        let world = world_with(|_, _| 0.0);
+        let direction = Vec3::new(1.0, -0.5, 0.0);
+        let hit = world.raycast(Vec3::new(-10.0, 10.25, 20.5), direction, 100.0);
+        assert_hit(hit, (10, 20), Vec3::new(10.5, 0.0, 20.5));
+        let direction = Vec3::new(-1.0, -1.0, -1.0);
+        assert_hit(world.raycast(Vec3::new(80.5, 30.0, 80.5), direction, 100.0), (50, 50), Vec3::new(50.5, 0.0, 50.5));
+        // Pointing away from the world, or down outside it.
+        assert!(world.raycast(Vec3::new(-10.0, 10.0, 20.5), -direction, 100.0).is_none());
+        assert!(world.raycast(Vec3::new(-10.0, 10.0, 20.5), Vec3::NEG_Y, 100.0).is_none());
+    }
+}